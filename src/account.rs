@@ -1,21 +1,36 @@
-use std::collections::BTreeMap;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, ensure, Result};
 use async_std::sync::{Arc, RwLock};
 use deltachat::{
-    chat::{self, Chat, ChatId},
+    chat::{self, Chat, ChatId, ChatVisibility, MuteDuration},
     chatlist::Chatlist,
-    constants::{Chattype, Viewtype},
+    config::Config,
+    constants::{
+        Chattype, Viewtype, DC_CHAT_ID_DEADDROP, DC_CONTACT_ID_SELF, DC_GCL_ARCHIVED_ONLY,
+        DC_GCL_NO_SPECIALS, DC_LP_IMAP_SOCKET_PLAIN, DC_LP_IMAP_SOCKET_SSL,
+        DC_LP_IMAP_SOCKET_STARTTLS, DC_LP_SMTP_SOCKET_PLAIN, DC_LP_SMTP_SOCKET_SSL,
+        DC_LP_SMTP_SOCKET_STARTTLS,
+    },
     contact::Contact,
     context::Context,
     message::{self, MessageState, MsgId},
-    EventEmitter,
+    Event, EventEmitter,
 };
 use lazy_static::lazy_static;
 use log::*;
 use time::OffsetDateTime;
 
+/// Number of messages loaded at once when paging through a chat.
+const PAGE_SIZE: usize = 50;
+/// Maximum number of messages kept in `AccountState::chat_msgs`.
+const MAX_LOADED_MSGS: usize = 4 * PAGE_SIZE;
+/// Maximum number of messages listed when searching all chats.
+const MAX_SEARCH_RESULTS: usize = 50;
+
 lazy_static! {
     pub static ref HOME_DIR: PathBuf = dirs::home_dir()
         .unwrap_or_else(|| "home".into())
@@ -38,11 +53,159 @@ pub struct AccountState {
     pub chatlist: Chatlist,
     /// Messages of the selected chat
     pub chat_msg_ids: Vec<MsgId>,
-    /// State of currently selected chat messages
+    /// State of currently selected chat messages, indexed by their position in `chat_msg_ids`
     pub chat_msgs: BTreeMap<usize, ChatMessage>,
+    /// The window of `chat_msg_ids` that is loaded into `chat_msgs`
     chat_msgs_range: (usize, usize),
     /// indexed by index in the Chatlist
     pub chats: BTreeMap<ChatId, Chat>,
+    pub contacts: ContactCache,
+    /// What the chat list shows
+    pub chat_filter: ChatListFilter,
+    /// Messages matching `chat_filter.query` in all chats
+    pub msg_search_results: Vec<MessageSearchResult>,
+    /// Message the user jumped to, e.g. from a search result
+    pub highlighted_msg: Option<MsgId>,
+    /// Unknown senders with fresh messages in the deaddrop, newest first
+    pub contact_requests: Vec<ContactRequest>,
+    /// Number of fresh messages across all chats
+    pub fresh_msg_cnt: usize,
+    /// Counts calls of `Account::refresh_chats`, which might finish out of order
+    refresh_generation: u64,
+    /// The refresh `chatlist` and `contact_requests` were loaded by
+    chatlist_generation: u64,
+}
+
+/// Someone unknown who wrote to this account, their messages wait in the deaddrop.
+#[derive(Debug, Clone)]
+pub struct ContactRequest {
+    /// The newest message of the contact
+    pub msg_id: MsgId,
+    pub contact_id: u32,
+    pub name: String,
+    pub preview: String,
+    pub timestamp: OffsetDateTime,
+    pub profile_image: Option<PathBuf>,
+    pub color: u32,
+    pub msg_cnt: usize,
+}
+
+/// What the chat list shows, see `Account::set_chat_filter`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChatListFilter {
+    /// Searches chat names, and messages in all chats
+    pub query: String,
+    pub unread_only: bool,
+    pub groups_only: bool,
+    /// Lists archived chats instead of the others
+    pub archived: bool,
+}
+
+impl ChatListFilter {
+    fn query(&self) -> Option<&str> {
+        Some(self.query.trim()).filter(|query| !query.is_empty())
+    }
+
+    fn flags(&self) -> usize {
+        if self.archived {
+            DC_GCL_ARCHIVED_ONLY
+        } else {
+            0
+        }
+    }
+
+    /// Applies the filters the core does not know about, they keep the archived chats link.
+    fn matches(&self, chat: &ChatState) -> bool {
+        if chat.id.is_archived_link() {
+            return true;
+        }
+
+        let is_group =
+            chat.chat_type == Chattype::Group || chat.chat_type == Chattype::VerifiedGroup;
+
+        (!self.unread_only || chat.fresh_msg_cnt > 0) && (!self.groups_only || is_group)
+    }
+}
+
+/// A message found by searching all chats.
+#[derive(Debug, Clone)]
+pub struct MessageSearchResult {
+    pub msg_id: MsgId,
+    pub chat_name: String,
+    pub from_first_name: String,
+    pub text: String,
+    pub timestamp: OffsetDateTime,
+}
+
+/// The parts of a contact needed to render chats and messages.
+#[derive(Debug, Clone)]
+pub struct CachedContact {
+    pub first_name: String,
+    pub display_name: String,
+    pub profile_image: Option<PathBuf>,
+    pub color: u32,
+}
+
+/// Contacts by id, filled lazily and cleared when the core reports `ContactsChanged`.
+#[derive(Debug, Clone, Default)]
+pub struct ContactCache {
+    contacts: Arc<RwLock<HashMap<u32, CachedContact>>>,
+}
+
+impl ContactCache {
+    pub async fn get(&self, context: &Context, contact_id: u32) -> Result<CachedContact> {
+        if let Some(contact) = self.contacts.read().await.get(&contact_id) {
+            return Ok(contact.clone());
+        }
+
+        let contact = Contact::load_from_db(context, contact_id)
+            .await
+            .map_err(|err| anyhow!("failed to load contact: {}: {}", contact_id, err))?;
+        let contact = CachedContact {
+            first_name: contact.get_first_name().to_string(),
+            display_name: contact.get_display_name().to_string(),
+            profile_image: contact.get_profile_image(context).await.map(Into::into),
+            color: contact.get_color(),
+        };
+
+        self.contacts
+            .write()
+            .await
+            .insert(contact_id, contact.clone());
+
+        Ok(contact)
+    }
+
+    pub async fn clear(&self) {
+        self.contacts.write().await.clear();
+    }
+}
+
+impl AccountState {
+    /// Whether there are messages before the loaded ones.
+    pub fn has_older_msgs(&self) -> bool {
+        self.chat_msgs_range.0 > 0
+    }
+
+    /// Whether there are messages after the loaded ones.
+    pub fn has_newer_msgs(&self) -> bool {
+        self.chat_msgs_range.1 < self.chat_msg_ids.len()
+    }
+
+    /// The states of all listed chats that match the filter, in the order of the chat list.
+    ///
+    /// The core lists pinned chats first already, but the archived chats link last, it is
+    /// moved to the top.
+    pub fn chat_list(&self) -> impl Iterator<Item = &ChatState> {
+        let chats = (0..self.chatlist.len())
+            .filter_map(move |i| self.chat_states.get(&self.chatlist.get_chat_id(i)))
+            .filter(move |chat| !chat.id.is_deaddrop() && self.chat_filter.matches(chat));
+
+        chats
+            .clone()
+            .filter(|chat| chat.id.is_archived_link())
+            .chain(chats.filter(|chat| !chat.id.is_archived_link()))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -61,6 +224,56 @@ pub struct ChatMessage {
     pub file: Option<PathBuf>,
     pub file_height: i32,
     pub file_width: i32,
+    /// Size of the attached file in bytes
+    pub file_bytes: u64,
+    /// Duration of audio and video files in milliseconds
+    pub duration: i32,
+    /// First frame of videos
+    pub poster: Option<PathBuf>,
+    /// Why sending failed, for messages in `MessageState::OutFailed`
+    pub error: Option<String>,
+    /// Identifies pending messages, their id is only set once the core accepted them
+    pub local_id: Option<u64>,
+}
+
+impl ChatMessage {
+    /// A message that was handed to the core, but not yet loaded back from the database.
+    pub fn pending(text: String) -> Self {
+        static NEXT_LOCAL_ID: AtomicU64 = AtomicU64::new(1);
+
+        ChatMessage {
+            id: MsgId::new_unset(),
+            from_id: DC_CONTACT_ID_SELF,
+            from_first_name: "Me".into(),
+            from_profile_image: None,
+            from_color: 0,
+            viewtype: Viewtype::Text,
+            state: MessageState::OutPending,
+            text: Some(text),
+            starred: false,
+            timestamp: OffsetDateTime::now_utc(),
+            is_info: false,
+            file: None,
+            file_height: 0,
+            file_width: 0,
+            file_bytes: 0,
+            duration: 0,
+            poster: None,
+            error: None,
+            local_id: Some(NEXT_LOCAL_ID.fetch_add(1, Ordering::Relaxed)),
+        }
+    }
+
+    /// Placeholder for an attachment that is being sent.
+    pub fn pending_file(attachment: &crate::media::Attachment, caption: Option<String>) -> Self {
+        ChatMessage {
+            viewtype: attachment.viewtype,
+            text: caption,
+            file: Some(attachment.path.clone()),
+            file_bytes: attachment.file_bytes,
+            ..ChatMessage::pending(String::new())
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -79,13 +292,251 @@ pub struct ChatState {
     pub is_device_talk: bool,
     pub chat_type: Chattype,
     pub color: u32,
+    pub visibility: ChatVisibility,
+    pub is_muted: bool,
+    /// The refresh this was loaded by, see `AccountState::refresh_generation`
+    pub generation: u64,
+}
+
+/// Path of the database for the given account.
+pub fn db_path(email: &str) -> PathBuf {
+    db_path_in(&HOME_DIR, email)
+}
+
+pub fn db_path_in(dir: &Path, email: &str) -> PathBuf {
+    dir.join(format!("{}.sqlite", encode_email(email)))
+}
+
+/// The form of an address accounts are stored and compared by.
+///
+/// Addresses differing only in case belong to the same mailbox, and would also share a
+/// database on case insensitive filesystems.
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// Encodes an email address into a file name that is valid on all common filesystems.
+///
+/// The address is lowercased, see `normalize_email`. Then everything but ASCII letters, digits
+/// and `@._+-` is percent encoded, as is a leading `.`, so the name can neither contain a path
+/// separator nor be `.` or `..`.
+pub fn encode_email(email: &str) -> String {
+    let email = email.to_lowercase();
+    let mut name = String::with_capacity(email.len());
+    for (i, byte) in email.bytes().enumerate() {
+        let keep = match byte {
+            b'.' => i > 0,
+            b'@' | b'_' | b'+' | b'-' => true,
+            _ => byte.is_ascii_alphanumeric(),
+        };
+        if keep {
+            name.push(byte as char);
+        } else {
+            name.push_str(&format!("%{:02X}", byte));
+        }
+    }
+
+    name
+}
+
+/// Reverses `encode_email`, returns `None` for names it did not produce.
+///
+/// Every address has exactly one name, so names with uppercase letters, lowercase hex digits or
+/// characters that should have been encoded are rejected.
+pub fn decode_email(name: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(name.len());
+    let mut input = name.bytes();
+    while let Some(byte) = input.next() {
+        if byte == b'%' {
+            let hex = [input.next()?, input.next()?];
+            let hex = std::str::from_utf8(&hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+
+    String::from_utf8(bytes)
+        .ok()
+        .filter(|email| encode_email(email) == name)
+}
+
+/// Where backups are imported into temporary accounts, see `Account::new_for_import`.
+pub fn import_dir_in(dir: &Path) -> PathBuf {
+    dir.join("import")
+}
+
+/// Moves a database together with its blobs and sqlite journal files.
+///
+/// Nothing is overwritten, an error is returned if any of the targets already exists.
+/// If moving one of the files fails, the ones already moved are moved back.
+pub async fn move_db(from: &Path, to: &Path) -> Result<()> {
+    let companions = |db: &Path| {
+        ["", "-blobs", "-wal", "-shm"]
+            .iter()
+            .map(|suffix| {
+                let mut path = db.as_os_str().to_owned();
+                path.push(suffix);
+                PathBuf::from(path)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let moves = companions(from)
+        .into_iter()
+        .zip(companions(to))
+        .filter(|(from, _)| from.exists())
+        .collect::<Vec<_>>();
+
+    for (_, to) in &moves {
+        ensure!(!to.exists(), "{} already exists", to.display());
+    }
+
+    if let Some(parent) = to.parent() {
+        async_std::fs::create_dir_all(parent).await?;
+    }
+    for (i, (from, to)) in moves.iter().enumerate() {
+        info!("moving {} to {}", from.display(), to.display());
+        if let Err(err) = async_std::fs::rename(from, to).await {
+            for (from, to) in moves[..i].iter().rev() {
+                if let Err(err) = async_std::fs::rename(to, from).await {
+                    error!("failed to move {} back: {}", to.display(), err);
+                }
+            }
+            bail!("failed to move {}: {}", from.display(), err);
+        }
+    }
+
+    Ok(())
+}
+
+/// Manually entered server settings, empty values are detected during configuration.
+#[derive(Debug, Clone, Default)]
+pub struct ServerSettings {
+    pub host: String,
+    pub port: String,
+    pub security: Security,
+    pub login: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Security {
+    Automatic,
+    Ssl,
+    StartTls,
+    Plain,
+}
+
+impl Default for Security {
+    fn default() -> Self {
+        Security::Automatic
+    }
+}
+
+impl Security {
+    pub const ALL: [Security; 4] = [
+        Security::Automatic,
+        Security::Ssl,
+        Security::StartTls,
+        Security::Plain,
+    ];
+
+    fn imap_flags(self) -> i32 {
+        match self {
+            Security::Automatic => 0,
+            Security::Ssl => DC_LP_IMAP_SOCKET_SSL,
+            Security::StartTls => DC_LP_IMAP_SOCKET_STARTTLS,
+            Security::Plain => DC_LP_IMAP_SOCKET_PLAIN,
+        }
+    }
+
+    fn smtp_flags(self) -> i32 {
+        match self {
+            Security::Automatic => 0,
+            Security::Ssl => DC_LP_SMTP_SOCKET_SSL,
+            Security::StartTls => DC_LP_SMTP_SOCKET_STARTTLS,
+            Security::Plain => DC_LP_SMTP_SOCKET_PLAIN,
+        }
+    }
+}
+
+impl std::fmt::Display for Security {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Security::Automatic => "Automatic",
+            Security::Ssl => "SSL/TLS",
+            Security::StartTls => "STARTTLS",
+            Security::Plain => "Off",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn non_empty(value: &str) -> Option<&str> {
+    let value = value.trim();
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// The core config the login sets, empty values are left to the configuration to detect.
+fn login_config(
+    email: &str,
+    password: &str,
+    imap: &ServerSettings,
+    smtp: &ServerSettings,
+) -> Vec<(Config, Option<String>)> {
+    let server_flags = imap.security.imap_flags() | smtp.security.smtp_flags();
+
+    vec![
+        (Config::Addr, Some(email.to_string())),
+        (Config::MailPw, Some(password.to_string())),
+        (Config::MailServer, non_empty(&imap.host).map(Into::into)),
+        (Config::MailPort, non_empty(&imap.port).map(Into::into)),
+        (Config::MailUser, non_empty(&imap.login).map(Into::into)),
+        (Config::SendServer, non_empty(&smtp.host).map(Into::into)),
+        (Config::SendPort, non_empty(&smtp.port).map(Into::into)),
+        (Config::SendUser, non_empty(&smtp.login).map(Into::into)),
+        (
+            Config::ServerFlags,
+            Some(server_flags)
+                .filter(|flags| *flags != 0)
+                .map(|flags| flags.to_string()),
+        ),
+    ]
 }
 
 impl Account {
     pub async fn new(email: &str) -> Result<Self> {
-        // TODO: escape email to be a vaild filesystem name
-        let path = HOME_DIR.join(format!("{}.sqlite", email));
+        Self::new_in(&HOME_DIR, email).await
+    }
 
+    /// Opens the account of the given address in `dir` instead of `HOME_DIR`.
+    pub async fn new_in(dir: &Path, email: &str) -> Result<Self> {
+        Self::open(email, db_path_in(dir, email)).await
+    }
+
+    /// Opens a temporary account to import a backup into, see `Account::import`.
+    pub async fn new_for_import() -> Result<Self> {
+        static NEXT_IMPORT: AtomicU64 = AtomicU64::new(0);
+
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|since| since.as_nanos())
+            .unwrap_or_default();
+        let path = import_dir_in(&HOME_DIR).join(format!(
+            "{}-{}-{}.sqlite",
+            std::process::id(),
+            nanos,
+            NEXT_IMPORT.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        Self::open("", path).await
+    }
+
+    async fn open(email: &str, path: PathBuf) -> Result<Self> {
         // Ensure the folders actually exist
         if let Some(parent) = path.parent() {
             async_std::fs::create_dir_all(parent).await?;
@@ -95,7 +546,10 @@ impl Account {
             .await
             .map_err(|err| anyhow!("{:?}", err))?;
 
-        context.start_io().await;
+        let logged_in = context.is_configured().await;
+        if logged_in {
+            context.start_io().await;
+        }
 
         let chatlist = Chatlist::try_load(&context, 0, None, None)
             .await
@@ -104,9 +558,10 @@ impl Account {
         let mut account = Account {
             context,
             state: Arc::new(RwLock::new(AccountState {
-                logged_in: true,
+                logged_in,
                 email: email.to_string(),
                 chats: Default::default(),
+                contacts: Default::default(),
                 selected_chat: None,
                 selected_chat_id: None,
                 chatlist,
@@ -114,10 +569,18 @@ impl Account {
                 chat_msg_ids: Default::default(),
                 chat_msgs: Default::default(),
                 chat_states: Default::default(),
+                chat_filter: Default::default(),
+                msg_search_results: Default::default(),
+                highlighted_msg: None,
+                contact_requests: Default::default(),
+                fresh_msg_cnt: 0,
+                refresh_generation: 0,
+                chatlist_generation: 0,
             })),
         };
 
         account.load_chat_list().await?;
+        account.refresh_fresh_msg_cnt().await;
 
         Ok(account)
     }
@@ -126,28 +589,168 @@ impl Account {
         self.state.read().await.logged_in
     }
 
+    /// Where the core keeps the files of messages.
+    pub fn blobdir(&self) -> PathBuf {
+        self.context.get_blobdir().to_path_buf()
+    }
+
     pub fn get_event_emitter(&self) -> EventEmitter {
         self.context.get_event_emitter()
     }
 
-    pub async fn import(&self, path: &str) -> Result<()> {
-        use deltachat::imex;
+    /// Waits until this is the last handle of the account, so its files can be touched.
+    ///
+    /// Other handles live in running commands and the event subscription, they are gone once
+    /// those finished or the account was removed from the app.
+    async fn wait_until_unused(&self) -> Result<()> {
+        const TIMEOUT: Duration = Duration::from_secs(10);
+
+        let start = Instant::now();
+        while Arc::strong_count(&self.state) > 1 {
+            ensure!(start.elapsed() < TIMEOUT, "account is still in use");
+            async_std::task::sleep(Duration::from_millis(50)).await;
+        }
+
+        Ok(())
+    }
+
+    /// Stops the account and deletes its database and blobs.
+    pub async fn delete(self) -> Result<()> {
+        self.context.stop_io().await;
+        self.wait_until_unused().await?;
+
+        let dbfile = self.context.get_dbfile().to_path_buf();
+        let blobdir = self.context.get_blobdir().to_path_buf();
+        drop(self);
 
-        imex::imex(&self.context, imex::ImexMode::ImportBackup, Some(path)).await?;
+        info!("deleting {}", dbfile.display());
+        if blobdir.exists() {
+            async_std::fs::remove_dir_all(&blobdir).await?;
+        }
+        for suffix in &["", "-wal", "-shm"] {
+            let mut path = dbfile.clone().into_os_string();
+            path.push(suffix);
+            let path = PathBuf::from(path);
+            if path.exists() {
+                async_std::fs::remove_file(&path).await?;
+            }
+        }
+
+        Ok(())
+    }
 
-        // TODO: start_io
+    /// Number of fresh messages across all chats.
+    pub async fn fresh_msg_cnt(&self) -> usize {
+        self.state.read().await.fresh_msg_cnt
+    }
+
+    async fn refresh_fresh_msg_cnt(&self) {
+        let fresh_msg_cnt = self.context.get_fresh_msgs().await.len();
+        self.state.write().await.fresh_msg_cnt = fresh_msg_cnt;
+    }
+
+    /// Shows a desktop notification for an incoming message.
+    pub async fn notify_incoming(&self, chat_id: ChatId, msg_id: MsgId) -> Result<()> {
+        let chat = Chat::load_from_db(&self.context, chat_id)
+            .await
+            .map_err(|err| anyhow!("failed to load chat: {:?}", err))?;
+        if chat.is_muted() {
+            return Ok(());
+        }
+
+        let msg = message::Message::load_from_db(&self.context, msg_id)
+            .await
+            .map_err(|err| anyhow!("failed to load msg: {}: {}", msg_id, err))?;
+
+        let email = self.state.read().await.email.clone();
+        let summary = format!("{} ({})", chat.get_name(), email);
+        let body = msg.get_summarytext(&self.context, 160).await;
+
+        async_std::task::spawn_blocking(move || {
+            notify_rust::Notification::new()
+                .summary(&summary)
+                .body(&body)
+                .show()
+                .map(|_| ())
+        })
+        .await
+        .map_err(|err| anyhow!("failed to show notification: {}", err))?;
 
         Ok(())
     }
 
-    pub async fn login(&mut self, email: &str, password: &str) -> Result<()> {
-        use deltachat::config::Config;
-        self.context.set_config(Config::Addr, Some(email)).await?;
-        self.context
-            .set_config(Config::MailPw, Some(password))
-            .await?;
+    /// Imports a backup into an account created with `Account::new_for_import`.
+    ///
+    /// Returns the address of the imported account, which is moved to its location by
+    /// `Account::finish_import`. On errors the account has to be deleted.
+    pub async fn import(&self, path: &Path) -> Result<String> {
+        use deltachat::imex;
+
+        // importing needs exclusive access to the database
+        self.context.stop_io().await;
+        imex::imex(&self.context, imex::ImexMode::ImportBackup, Some(path))
+            .await
+            .map_err(|err| anyhow!("failed to import backup: {}", err))?;
+
+        let email = self
+            .context
+            .get_config(Config::Addr)
+            .await
+            .ok_or_else(|| anyhow!("the backup does not contain an account"))?;
+        ensure!(!db_path(&email).exists(), "{} already exists", email);
+
+        Ok(email)
+    }
+
+    /// Moves an imported account to the location for its address and reopens it there.
+    ///
+    /// Waits until every other handle of the account is gone, the account is deleted if it can
+    /// not be moved.
+    pub async fn finish_import(self, email: String) -> Result<(String, Account)> {
+        let email = normalize_email(&email);
+        let dbfile = self.context.get_dbfile().to_path_buf();
+        let moved = match self.wait_until_unused().await {
+            Ok(()) => move_db(&dbfile, &db_path(&email)).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = moved {
+            if let Err(err) = self.delete().await {
+                warn!("failed to delete imported account: {}", err);
+            }
+            return Err(err);
+        }
+        drop(self);
+
+        // opening starts the io, as the account is configured now
+        let account = Account::new(&email).await?;
+
+        Ok((email, account))
+    }
+
+    /// Writes a backup into the given directory.
+    pub async fn export(&self, dir: &Path) -> Result<()> {
+        use deltachat::imex;
+
+        self.context.stop_io().await;
+        let res = imex::imex(&self.context, imex::ImexMode::ExportBackup, Some(dir)).await;
+        self.context.start_io().await;
+
+        res.map_err(|err| anyhow!("failed to export backup: {}", err))
+    }
+
+    pub async fn login(
+        &mut self,
+        email: &str,
+        password: &str,
+        imap: &ServerSettings,
+        smtp: &ServerSettings,
+    ) -> Result<()> {
+        for (key, value) in login_config(email, password, imap, smtp) {
+            self.context.set_config(key, value.as_deref()).await?;
+        }
 
         self.configure().await?;
+        self.context.start_io().await;
         self.state.write().await.logged_in = true;
 
         Ok(())
@@ -160,30 +763,351 @@ impl Account {
     }
 
     pub async fn load_chat_list(&mut self) -> Result<()> {
+        self.refresh_chats(None).await
+    }
+
+    /// Reloads the chat list, and the state of the given chats or of all chats if `None`.
+    ///
+    /// Chats that are new in the list are always loaded. The state lock is only taken
+    /// to swap in the results, so readers are not blocked while the chats are loading.
+    /// Results of a refresh that started before an already applied one are dropped.
+    pub async fn refresh_chats(&self, chat_ids: Option<&[ChatId]>) -> Result<()> {
+        info!("refreshing chats: {:?}", chat_ids);
+
+        let (generation, filter) = {
+            let state = &mut *self.state.write().await;
+            state.refresh_generation += 1;
+            (state.refresh_generation, state.chat_filter.clone())
+        };
+        let chatlist = Chatlist::try_load(&self.context, filter.flags(), filter.query(), None)
+            .await
+            .map_err(|err| anyhow!("failed to load chats: {:?}", err))?;
+        let listed = (0..chatlist.len()).map(|i| chatlist.get_chat_id(i));
+
+        let chat_ids: Vec<ChatId> = match chat_ids {
+            Some(chat_ids) => {
+                let state = self.state.read().await;
+                listed
+                    .filter(|chat_id| {
+                        chat_ids.contains(chat_id) || !state.chat_states.contains_key(chat_id)
+                    })
+                    .collect()
+            }
+            None => listed.collect(),
+        };
+
+        let contacts = self.state.read().await.contacts.clone();
+        let mut loaded = Vec::with_capacity(chat_ids.len());
+        for chat_id in chat_ids {
+            loaded.push(load_chat_state(&self.context, &chatlist, chat_id).await?);
+        }
+        let contact_requests = load_contact_requests(&self.context, &contacts).await?;
+
         let state = &mut *self.state.write().await;
-        state.chat_states.clear();
+        if generation > state.chatlist_generation {
+            state.chatlist_generation = generation;
+            state.contact_requests = contact_requests;
+
+            // Drop chats that are gone and update the position of the others.
+            state
+                .chat_states
+                .retain(|chat_id, _| chatlist.get_index_for_id(*chat_id).is_some());
+            for (chat_id, chat_state) in state.chat_states.iter_mut() {
+                chat_state.index = chatlist.get_index_for_id(*chat_id);
+            }
+            state.chatlist = chatlist;
+        }
 
-        for i in 0..state.chatlist.len() {
-            let chat_id = state.chatlist.get_chat_id(i);
-            refresh_chat_state(self.context.clone(), state, chat_id).await?;
+        for (chat, chat_state) in loaded {
+            let chat_state = chat_state.and_then(|mut chat_state| {
+                // A newer refresh might have loaded the chat already, or swapped in a list
+                // the chat is at another position in, or not in at all.
+                let newer = state
+                    .chat_states
+                    .get(&chat_state.id)
+                    .map(|current| current.generation > generation)
+                    .unwrap_or_default();
+                if newer {
+                    return None;
+                }
+                chat_state.generation = generation;
+                chat_state.index = state.chatlist.get_index_for_id(chat_state.id);
+                Some(chat_state)
+            });
+            apply_chat_state(state, chat, chat_state);
         }
 
         Ok(())
     }
 
-    pub async fn select_chat(&mut self, chat_id: ChatId) -> Result<()> {
-        info!("selecting chat {:?}", chat_id);
+    /// Archives, pins or restores a chat on this device.
+    pub async fn set_chat_visibility(
+        &self,
+        chat_id: ChatId,
+        visibility: ChatVisibility,
+    ) -> Result<()> {
+        chat_id
+            .set_visibility(&self.context, visibility)
+            .await
+            .map_err(|err| anyhow!("failed to change visibility: {:?}", err))?;
+
+        self.refresh_chats(None).await
+    }
+
+    pub async fn set_chat_mute(&self, chat_id: ChatId, duration: MuteDuration) -> Result<()> {
+        chat::set_muted(&self.context, chat_id, duration)
+            .await
+            .map_err(|err| anyhow!("failed to mute chat: {:?}", err))?;
+
+        self.refresh_chats(Some(&[chat_id])).await
+    }
+
+    /// Deletes all messages of a chat on this device and the server, but keeps the chat.
+    pub async fn clear_chat_history(&self, chat_id: ChatId) -> Result<()> {
+        let msg_ids = chat::get_chat_msgs(&self.context, chat_id, 0, None).await;
+        message::delete_msgs(&self.context, &msg_ids).await;
+
+        self.refresh_chats(Some(&[chat_id])).await?;
+        self.refresh_messages(Some(chat_id), None).await
+    }
+
+    /// Blocks the contact of a 1:1 chat.
+    pub async fn block_chat_contact(&self, chat_id: ChatId) -> Result<()> {
+        let chat = Chat::load_from_db(&self.context, chat_id)
+            .await
+            .map_err(|err| anyhow!("failed to load chat: {:?}", err))?;
+        ensure!(
+            chat.get_type() == Chattype::Single,
+            "only contacts of 1:1 chats can be blocked"
+        );
+
+        for contact_id in chat::get_chat_contacts(&self.context, chat_id).await {
+            Contact::block(&self.context, contact_id).await;
+        }
+
+        self.refresh_chats(None).await
+    }
+
+    pub async fn leave_group(&self, chat_id: ChatId) -> Result<()> {
+        chat::remove_contact_from_chat(&self.context, chat_id, DC_CONTACT_ID_SELF)
+            .await
+            .map_err(|err| anyhow!("failed to leave group: {:?}", err))?;
+
+        self.refresh_chats(Some(&[chat_id])).await
+    }
+
+    /// Deletes a chat with all its messages, deselecting it if it is selected.
+    pub async fn delete_chat(&self, chat_id: ChatId) -> Result<()> {
+        chat_id
+            .delete(&self.context)
+            .await
+            .map_err(|err| anyhow!("failed to delete chat: {:?}", err))?;
+
+        {
+            let state = &mut *self.state.write().await;
+            if state.selected_chat_id == Some(chat_id) {
+                state.selected_chat_id = None;
+                state.selected_chat = None;
+                state.chat_msg_ids = Default::default();
+                state.chat_msgs = Default::default();
+                state.chat_msgs_range = (0, 0);
+            }
+        }
+
+        self.refresh_chats(None).await
+    }
+
+    /// Changes what the chat list shows, searching messages of all chats for the query.
+    pub async fn set_chat_filter(&self, filter: ChatListFilter) -> Result<()> {
+        let (query_changed, archived_changed) = {
+            let state = &mut *self.state.write().await;
+            let changed = (
+                state.chat_filter.query() != filter.query(),
+                state.chat_filter.archived != filter.archived,
+            );
+            state.chat_filter = filter.clone();
+            changed
+        };
+
+        // The other filters are applied to the loaded chats, see `AccountState::chat_list`.
+        if query_changed || archived_changed {
+            self.refresh_chats(None).await?;
+        }
+        if query_changed {
+            let results = match filter.query() {
+                Some(query) => self.search_messages(query).await?,
+                None => Vec::new(),
+            };
+
+            // The query might have changed again while searching.
+            let state = &mut *self.state.write().await;
+            if state.chat_filter.query() == filter.query() {
+                state.msg_search_results = results;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn search_messages(&self, query: &str) -> Result<Vec<MessageSearchResult>> {
+        let msg_ids = self.context.search_msgs(ChatId::new(0), query).await;
+        let contacts = self.state.read().await.contacts.clone();
+
+        let mut results = Vec::new();
+        for msg_id in msg_ids.into_iter().take(MAX_SEARCH_RESULTS) {
+            let msg = message::Message::load_from_db(&self.context, msg_id)
+                .await
+                .map_err(|err| anyhow!("failed to load msg: {}: {}", msg_id, err))?;
+            let chat = Chat::load_from_db(&self.context, msg.get_chat_id())
+                .await
+                .map_err(|err| anyhow!("failed to load chat: {:?}", err))?;
+            let from = contacts.get(&self.context, msg.get_from_id()).await?;
+
+            results.push(MessageSearchResult {
+                msg_id,
+                chat_name: chat.get_name().to_string(),
+                from_first_name: from.first_name,
+                text: msg.get_text().unwrap_or_default(),
+                timestamp: OffsetDateTime::from_unix_timestamp(msg.get_sort_timestamp()),
+            });
+        }
+
+        Ok(results)
+    }
+
+    /// Selects the chat of the message and loads the messages around it.
+    pub async fn jump_to_message(&mut self, msg_id: MsgId) -> Result<()> {
+        let msg = message::Message::load_from_db(&self.context, msg_id)
+            .await
+            .map_err(|err| anyhow!("failed to load msg: {}: {}", msg_id, err))?;
+        self.select_chat(msg.get_chat_id()).await?;
+
         let state = &mut *self.state.write().await;
-        let (chat, chat_state) = load_chat_state(self.context.clone(), state, chat_id).await?;
+        state.chat_msg_ids = chat::get_chat_msgs(&self.context, msg.get_chat_id(), 0, None).await;
+        let index = state
+            .chat_msg_ids
+            .iter()
+            .position(|id| *id == msg_id)
+            .ok_or_else(|| anyhow!("message {} is not in its chat", msg_id))?;
+
+        let len = state.chat_msg_ids.len();
+        let start = index.saturating_sub(PAGE_SIZE / 2);
+        let end = (start + PAGE_SIZE).min(len);
+        state.highlighted_msg = Some(msg_id);
+        load_message_window(self.context.clone(), state, (start, end)).await?;
+
+        let msgs_list = state.chat_msgs.values().map(|msg| msg.id).collect();
+        message::markseen_msgs(&self.context, msgs_list).await;
 
-        state.selected_chat_id = Some(chat_id);
-        state.chat_msg_ids = chat::get_chat_msgs(&self.context, chat_id, 0, None).await;
-        state.chat_msgs = Default::default();
+        Ok(())
+    }
+
+    /// Reloads the messages of the selected chat, see `refresh_message_list`.
+    async fn refresh_messages(
+        &self,
+        chat_id: Option<ChatId>,
+        changed: Option<MsgId>,
+    ) -> Result<()> {
+        let state = &mut *self.state.write().await;
+        refresh_message_list(self.context.clone(), state, chat_id, changed).await
+    }
+
+    /// Reloads a single message if it is loaded, e.g. because its state changed.
+    async fn refresh_message(&self, msg_id: MsgId) -> Result<()> {
+        let state = &mut *self.state.write().await;
+        let index = state
+            .chat_msgs
+            .iter()
+            .find(|(_, msg)| msg.id == msg_id)
+            .map(|(i, _)| *i);
+
+        if let Some(i) = index {
+            let chat_msg = load_chat_message(&self.context, &state.contacts, msg_id).await?;
+            state.chat_msgs.insert(i, chat_msg);
+        }
+
+        Ok(())
+    }
+
+    /// Applies an event emitted by the core to the account state.
+    ///
+    /// Returns `true` if the state changed and views need to be rebuilt.
+    pub async fn handle_event(&self, event: &Event) -> Result<bool> {
+        match event {
+            Event::IncomingMsg { chat_id, msg_id } => {
+                self.refresh_chats(Some(&[*chat_id])).await?;
+                self.refresh_messages(Some(*chat_id), Some(*msg_id)).await?;
+
+                if self.state.read().await.selected_chat_id == Some(*chat_id) {
+                    // the chat is open, so the user is seeing this message
+                    message::markseen_msgs(&self.context, vec![*msg_id]).await;
+                }
+            }
+            Event::MsgsChanged { chat_id, msg_id } => {
+                // without an id any message might have changed
+                let changed = Some(*msg_id).filter(|msg_id| !msg_id.is_unset());
+                if chat_id.is_unset() {
+                    // any chat might have changed
+                    self.refresh_chats(None).await?;
+                    self.refresh_messages(None, changed).await?;
+                } else {
+                    self.refresh_chats(Some(&[*chat_id])).await?;
+                    self.refresh_messages(Some(*chat_id), changed).await?;
+                }
+            }
+            Event::ChatModified(chat_id) => {
+                self.refresh_chats(Some(&[*chat_id])).await?;
+            }
+            Event::MsgDelivered { chat_id, msg_id }
+            | Event::MsgRead { chat_id, msg_id }
+            | Event::MsgFailed { chat_id, msg_id } => {
+                // only the state changed, no need to reload the whole window
+                self.refresh_chats(Some(&[*chat_id])).await?;
+                self.refresh_message(*msg_id).await?;
+            }
+            Event::ContactsChanged(_) => {
+                // names and avatars might be shown anywhere
+                let contacts = self.state.read().await.contacts.clone();
+                contacts.clear().await;
+                self.refresh_chats(None).await?;
+                self.refresh_messages(None, None).await?;
+            }
+            _ => return Ok(false),
+        }
+        self.refresh_fresh_msg_cnt().await;
+
+        Ok(true)
+    }
 
+    pub async fn select_chat(&mut self, chat_id: ChatId) -> Result<()> {
+        info!("selecting chat {:?}", chat_id);
         // mark as noticed
         chat::marknoticed_chat(&self.context, chat_id)
             .await
             .map_err(|err| anyhow!("failed to mark noticed: {:?}", err))?;
+        self.refresh_fresh_msg_cnt().await;
+
+        let chatlist = self.state.read().await.chatlist.clone();
+        let (chat, mut chat_state) = load_chat_state(&self.context, &chatlist, chat_id).await?;
+        if chat_state.is_none() {
+            // The chat is filtered out of the list, e.g. when jumping to a search result.
+            for flags in &[0, DC_GCL_ARCHIVED_ONLY] {
+                let chatlist = Chatlist::try_load(&self.context, *flags, None, None)
+                    .await
+                    .map_err(|err| anyhow!("failed to load chats: {:?}", err))?;
+                if chatlist.get_index_for_id(chat_id).is_some() {
+                    chat_state = load_chat_state(&self.context, &chatlist, chat_id).await?.1;
+                    break;
+                }
+            }
+        }
+
+        let state = &mut *self.state.write().await;
+        state.selected_chat_id = Some(chat_id);
+        state.highlighted_msg = None;
+        state.chat_msg_ids = Default::default();
+        state.chat_msgs = Default::default();
+        state.chat_msgs_range = (0, 0);
 
         if let Some(chat_state) = chat_state {
             state.selected_chat = Some(chat_state);
@@ -197,123 +1121,271 @@ impl Account {
     pub async fn load_message_list(&mut self) -> Result<()> {
         let state = &mut *self.state.write().await;
 
-        refresh_message_list(self.context.clone(), state, None).await?;
+        refresh_message_list(self.context.clone(), state, None, None).await?;
 
         // markseen messages that we load
         // could be better, by checking actual in view, but close enough for now
-        let msgs_list = state.chat_msg_ids.clone();
+        let msgs_list = state.chat_msgs.values().map(|msg| msg.id).collect();
         message::markseen_msgs(&self.context, msgs_list).await;
 
         Ok(())
     }
 
-    pub async fn send_text_message(&self, text: String) -> Result<()> {
-        if let Some(chat_id) = self.state.read().await.selected_chat_id {
-            chat::send_text_msg(&self.context, chat_id, text)
-                .await
-                .map_err(|err| anyhow!("failed to send message: {}", err))?;
-        } else {
-            bail!("no chat selected, can not send message");
+    /// Loads the newest page of messages, e.g. to show a message that was just sent.
+    pub async fn load_newest_messages(&self) -> Result<()> {
+        let state = &mut *self.state.write().await;
+
+        let len = state.chat_msg_ids.len();
+        let range = (len.saturating_sub(PAGE_SIZE), len);
+        load_message_window(self.context.clone(), state, range).await
+    }
+
+    /// Loads the page of messages before the loaded ones, dropping the newest if too many are loaded.
+    pub async fn load_older_messages(&self) -> Result<()> {
+        let state = &mut *self.state.write().await;
+
+        let (start, end) = state.chat_msgs_range;
+        let new_start = start.saturating_sub(PAGE_SIZE);
+        let new_end = end.min(new_start + MAX_LOADED_MSGS);
+        load_message_window(self.context.clone(), state, (new_start, new_end)).await
+    }
+
+    /// Loads the page of messages after the loaded ones, dropping the oldest if too many are loaded.
+    pub async fn load_newer_messages(&self) -> Result<()> {
+        let state = &mut *self.state.write().await;
+
+        let (start, end) = state.chat_msgs_range;
+        let new_end = (end + PAGE_SIZE).min(state.chat_msg_ids.len());
+        let new_start = start.max(new_end.saturating_sub(MAX_LOADED_MSGS));
+        load_message_window(self.context.clone(), state, (new_start, new_end)).await
+    }
+
+    /// Sends a failed message again as a new message, the failed one is deleted.
+    pub async fn retry_message(&self, msg_id: MsgId) -> Result<()> {
+        let failed = message::Message::load_from_db(&self.context, msg_id)
+            .await
+            .map_err(|err| anyhow!("failed to load msg: {}: {}", msg_id, err))?;
+        ensure!(
+            failed.get_state() == MessageState::OutFailed,
+            "message {} did not fail",
+            msg_id
+        );
+
+        let mut msg = message::Message::new(failed.get_viewtype());
+        msg.set_text(failed.get_text());
+        if let Some(file) = failed.get_file(&self.context) {
+            msg.set_file(file.to_string_lossy(), failed.get_filemime().as_deref());
         }
 
+        chat::send_msg(&self.context, failed.get_chat_id(), &mut msg)
+            .await
+            .map_err(|err| anyhow!("failed to send message: {}", err))?;
+        message::delete_msgs(&self.context, &[msg_id]).await;
+
         Ok(())
     }
 
+    pub async fn star_message(&self, msg_id: MsgId, star: bool) -> Result<()> {
+        ensure!(
+            message::star_msgs(&self.context, vec![msg_id], star).await,
+            "failed to star message {}",
+            msg_id
+        );
+
+        self.refresh_message(msg_id).await
+    }
+
+    pub async fn forward_message(&self, msg_id: MsgId, chat_id: ChatId) -> Result<()> {
+        chat::forward_msgs(&self.context, &[msg_id], chat_id)
+            .await
+            .map_err(|err| anyhow!("failed to forward message: {:?}", err))
+    }
+
+    /// Chats a message can be forwarded to, independent of the filter of the chat list.
+    pub async fn forward_targets(&self) -> Result<Vec<(ChatId, String)>> {
+        let chatlist = Chatlist::try_load(&self.context, DC_GCL_NO_SPECIALS, None, None)
+            .await
+            .map_err(|err| anyhow!("failed to load chats: {:?}", err))?;
+
+        let mut targets = Vec::new();
+        for i in 0..chatlist.len() {
+            let chat_id = chatlist.get_chat_id(i);
+            let chat = Chat::load_from_db(&self.context, chat_id)
+                .await
+                .map_err(|err| anyhow!("failed to load chat: {}: {}", chat_id, err))?;
+            if chat.can_send() {
+                targets.push((chat_id, chat.get_name().to_string()));
+            }
+        }
+
+        Ok(targets)
+    }
+
+    /// Deletes a message on this device and the server, other members still keep it.
+    pub async fn delete_message(&self, msg_id: MsgId) -> Result<()> {
+        let chat_id = message::Message::load_from_db(&self.context, msg_id)
+            .await
+            .map_err(|err| anyhow!("failed to load msg: {}: {}", msg_id, err))?
+            .get_chat_id();
+        message::delete_msgs(&self.context, &[msg_id]).await;
+
+        self.refresh_chats(Some(&[chat_id])).await?;
+        self.refresh_messages(Some(chat_id), None).await
+    }
+
+    /// Technical details of a message as the core reports them.
+    pub async fn message_info(&self, msg_id: MsgId) -> String {
+        message::get_msg_info(&self.context, msg_id).await
+    }
+
+    pub async fn send_text_message(&self, chat_id: ChatId, text: String) -> Result<MsgId> {
+        chat::send_text_msg(&self.context, chat_id, text)
+            .await
+            .map_err(|err| anyhow!("failed to send message: {}", err))
+    }
+
     pub async fn send_file_message(
         &self,
+        chat_id: ChatId,
         typ: Viewtype,
         path: String,
         text: Option<String>,
         mime: Option<String>,
-    ) -> Result<()> {
-        if let Some(chat_id) = self.state.read().await.selected_chat_id {
-            let mut msg = message::Message::new(typ);
-            msg.set_text(text);
-            msg.set_file(path, mime.as_deref());
+    ) -> Result<MsgId> {
+        let mut msg = message::Message::new(typ);
+        msg.set_text(text);
+        msg.set_file(path, mime.as_deref());
 
-            chat::send_msg(&self.context, chat_id, &mut msg)
-                .await
-                .map_err(|err| anyhow!("failed to send message: {}", err))?;
-        } else {
-            bail!("no chat selected, can not send message");
-        }
-
-        Ok(())
+        chat::send_msg(&self.context, chat_id, &mut msg)
+            .await
+            .map_err(|err| anyhow!("failed to send message: {}", err))
     }
 
+    /// Creates a chat with the sender of a message, e.g. to accept a contact request.
+    ///
+    /// The chat is not selected, use `select_chat` for that.
     pub async fn create_chat_by_id(&self, id: MsgId) -> Result<ChatId> {
         let chat = chat::create_by_msg_id(&self.context, id)
             .await
             .map_err(|err| anyhow!("failed to create chat: {}", err))?;
 
-        // TODO: select that chat?
+        self.refresh_chats(None).await?;
         Ok(chat)
     }
 
+    pub async fn block_contact(&self, contact_id: u32) -> Result<()> {
+        Contact::block(&self.context, contact_id).await;
+        self.refresh_chats(None).await
+    }
+
+    /// Marks the messages of a contact request as noticed, so it is no longer listed.
+    pub async fn ignore_contact_request(&self, contact_id: u32) -> Result<()> {
+        Contact::mark_noticed(&self.context, contact_id).await;
+        self.refresh_chats(None).await
+    }
+
     pub async fn maybe_network(&self) {
         self.context.maybe_network().await;
     }
 }
 
-pub async fn refresh_chat_state(
-    context: Context,
-    state: &mut AccountState,
-    chat_id: ChatId,
-) -> Result<()> {
-    info!("refreshing chat state: {:?}", &chat_id);
+/// Groups the fresh messages in the deaddrop by their sender.
+async fn load_contact_requests(
+    context: &Context,
+    contacts: &ContactCache,
+) -> Result<Vec<ContactRequest>> {
+    // Fresh messages come newest first, only those waiting in the deaddrop are requests.
+    let fresh_msg_ids = context.get_fresh_msgs().await;
+    if fresh_msg_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let deaddrop: HashSet<MsgId> =
+        chat::get_chat_msgs(context, ChatId::new(DC_CHAT_ID_DEADDROP), 0, None)
+            .await
+            .into_iter()
+            .collect();
 
-    let (chat, chat_state) = load_chat_state(context, state, chat_id).await?;
+    let mut requests: Vec<ContactRequest> = Vec::new();
+    for msg_id in fresh_msg_ids.into_iter().filter(|id| deaddrop.contains(id)) {
+        let msg = message::Message::load_from_db(context, msg_id)
+            .await
+            .map_err(|err| anyhow!("failed to load msg: {}: {}", msg_id, err))?;
 
+        let contact_id = msg.get_from_id();
+        if let Some(request) = requests.iter_mut().find(|r| r.contact_id == contact_id) {
+            request.msg_cnt += 1;
+            continue;
+        }
+
+        let contact = contacts.get(context, contact_id).await?;
+        requests.push(ContactRequest {
+            msg_id,
+            contact_id,
+            name: contact.display_name,
+            preview: msg.get_summarytext(context, 80).await,
+            timestamp: OffsetDateTime::from_unix_timestamp(msg.get_sort_timestamp()),
+            profile_image: contact.profile_image,
+            color: contact.color,
+            msg_cnt: 1,
+        });
+    }
+
+    Ok(requests)
+}
+
+/// Stores a loaded chat state, also updating the selected chat with it.
+fn apply_chat_state(state: &mut AccountState, chat: Chat, chat_state: Option<ChatState>) {
     if let Some(chat_state) = chat_state {
         if let Some(sel_chat_id) = state.selected_chat_id {
-            if sel_chat_id == chat_id {
+            if sel_chat_id == chat_state.id {
                 state.selected_chat = Some(chat_state.clone());
             }
         }
 
         if chat_state.index.is_some() {
             // Only insert if there is actually a valid index.
-            state.chat_states.insert(chat_id, chat_state);
+            state.chat_states.insert(chat_state.id, chat_state);
         }
     }
     state.chats.insert(chat.id, chat);
-
-    Ok(())
 }
 
 async fn load_chat_state(
-    context: Context,
-    state: &AccountState,
+    context: &Context,
+    chats: &Chatlist,
     chat_id: ChatId,
 ) -> Result<(Chat, Option<ChatState>)> {
-    let chats = &state.chatlist;
-    let chat = Chat::load_from_db(&context, chat_id)
+    let chat = Chat::load_from_db(context, chat_id)
         .await
         .map_err(|err| anyhow!("failed to load chats: {:?}", err))?;
 
     let chat_state = if let Some(index) = chats.get_index_for_id(chat_id) {
-        let lot = chats.get_summary(&context, index, Some(&chat)).await;
+        let lot = chats.get_summary(context, index, Some(&chat)).await;
+
+        let profile_image = chat.get_profile_image(context).await.map(Into::into);
+        let color = chat.get_color(context).await;
 
         let header = lot.get_text1().map(|s| s.to_string()).unwrap_or_default();
         let preview = lot.get_text2().map(|s| s.to_string()).unwrap_or_default();
 
-        let index = state.chatlist.get_index_for_id(chat_id);
-
         Some(ChatState {
             id: chat_id,
-            index,
+            index: Some(index),
             name: chat.get_name().to_string(),
             header,
             preview,
             timestamp: OffsetDateTime::from_unix_timestamp(lot.get_timestamp()),
             state: lot.get_state().to_string(),
-            profile_image: chat.get_profile_image(&context).await.map(Into::into),
+            profile_image,
             can_send: chat.can_send(),
             chat_type: chat.get_type(),
-            color: chat.get_color(&context).await,
+            color,
+            visibility: chat.get_visibility(),
+            is_muted: chat.is_muted(),
             is_device_talk: chat.is_device_talk(),
             is_self_talk: chat.is_self_talk(),
-            fresh_msg_cnt: chat_id.get_fresh_msg_cnt(&context).await,
+            fresh_msg_cnt: chat_id.get_fresh_msg_cnt(context).await,
+            generation: 0,
         })
     } else {
         None
@@ -322,20 +1394,14 @@ async fn load_chat_state(
     Ok((chat, chat_state))
 }
 
-pub async fn refresh_chat_list(context: Context, state: &mut AccountState) -> Result<()> {
-    let chatlist = Chatlist::try_load(&context, 0, None, None)
-        .await
-        .map_err(|err| anyhow!("failed to load chats: {:?}", err))?;
-
-    state.chatlist = chatlist;
-
-    Ok(())
-}
-
+/// Reloads the message ids of the selected chat, if it is `chat_id` or `chat_id` is `None`.
+///
+/// Loaded messages are kept, except `changed`, all of them are reloaded if it is `None`.
 pub async fn refresh_message_list(
     context: Context,
     state: &mut AccountState,
     chat_id: Option<ChatId>,
+    changed: Option<MsgId>,
 ) -> Result<()> {
     let current_chat_id = state.selected_chat_id.clone();
     if chat_id.is_some() && current_chat_id != chat_id {
@@ -348,38 +1414,328 @@ pub async fn refresh_message_list(
 
     info!("loading chat messages {:?}", chat_id,);
 
+    let old_ids = std::mem::take(&mut state.chat_msg_ids);
+    let (start, end) = state.chat_msgs_range;
     state.chat_msg_ids = chat::get_chat_msgs(&context, current_chat_id.unwrap(), 0, None).await;
+    let len = state.chat_msg_ids.len();
 
-    let mut msgs = BTreeMap::new();
-    for (i, msg_id) in state.chat_msg_ids.iter().enumerate() {
-        let msg = message::Message::load_from_db(&context, *msg_id)
-            .await
-            .map_err(|err| anyhow!("failed to load msg: {}: {}", msg_id, err))?;
+    let range = if end >= old_ids.len() {
+        // Showing the newest messages, keep following them.
+        let size = (end - start).max(PAGE_SIZE);
+        (len.saturating_sub(size), len)
+    } else {
+        // Keep the first loaded message in place, even if messages before it were deleted.
+        let new_start = old_ids
+            .get(start)
+            .and_then(|id| state.chat_msg_ids.iter().position(|new_id| new_id == id))
+            .unwrap_or(start)
+            .min(len);
+        (new_start, (new_start + end - start).min(len))
+    };
 
-        let from = Contact::load_from_db(&context, msg.get_from_id())
-            .await
-            .map_err(|err| anyhow!("failed to load contact: {}: {}", msg.get_from_id(), err))?;
-
-        let chat_msg = ChatMessage {
-            id: msg.get_id(),
-            from_id: msg.get_from_id(),
-            viewtype: msg.get_viewtype(),
-            from_first_name: from.get_first_name().to_string(),
-            from_profile_image: from.get_profile_image(&context).await.map(Into::into),
-            from_color: from.get_color(),
-            starred: msg.is_starred(),
-            state: msg.get_state(),
-            text: msg.get_text(),
-            timestamp: OffsetDateTime::from_unix_timestamp(msg.get_sort_timestamp()),
-            is_info: msg.is_info(),
-            file: msg.get_file(&context).map(Into::into),
-            file_width: msg.get_width(),
-            file_height: msg.get_height(),
-        };
-        msgs.insert(i, chat_msg);
+    // Messages moved if others were added or deleted before them.
+    let mut loaded = std::mem::take(&mut state.chat_msgs)
+        .into_iter()
+        .map(|(_, msg)| (msg.id, msg))
+        .collect::<HashMap<_, _>>();
+    match changed {
+        Some(msg_id) => {
+            loaded.remove(&msg_id);
+        }
+        None => loaded.clear(),
     }
+    state.chat_msgs = (range.0..range.1)
+        .filter_map(|i| loaded.remove(&state.chat_msg_ids[i]).map(|msg| (i, msg)))
+        .collect();
 
-    state.chat_msgs = msgs;
+    load_message_window(context, state, range).await
+}
+
+/// Makes `range` the loaded window of messages, only loading the ones that are not loaded yet.
+async fn load_message_window(
+    context: Context,
+    state: &mut AccountState,
+    range: (usize, usize),
+) -> Result<()> {
+    let (start, end) = range;
+    let contacts = state.contacts.clone();
+    state.chat_msgs = std::mem::take(&mut state.chat_msgs)
+        .into_iter()
+        .filter(|(i, _)| *i >= start && *i < end)
+        .collect();
+
+    for i in start..end {
+        if state.chat_msgs.contains_key(&i) {
+            continue;
+        }
+        let chat_msg = load_chat_message(&context, &contacts, state.chat_msg_ids[i]).await?;
+        state.chat_msgs.insert(i, chat_msg);
+    }
+    state.chat_msgs_range = range;
 
     Ok(())
 }
+
+async fn load_chat_message(
+    context: &Context,
+    contacts: &ContactCache,
+    msg_id: MsgId,
+) -> Result<ChatMessage> {
+    let msg = message::Message::load_from_db(context, msg_id)
+        .await
+        .map_err(|err| anyhow!("failed to load msg: {}: {}", msg_id, err))?;
+
+    let from = contacts.get(context, msg.get_from_id()).await?;
+
+    let file: Option<PathBuf> = msg.get_file(context).map(Into::into);
+    let poster = match (&file, msg.get_viewtype()) {
+        // generated in the background by the app, see `media::video_poster`
+        (Some(file), Viewtype::Video) => crate::media::cached_video_poster(file),
+        _ => None,
+    };
+    let error = if msg.get_state() == MessageState::OutFailed {
+        msg.error()
+    } else {
+        None
+    };
+
+    Ok(ChatMessage {
+        id: msg.get_id(),
+        from_id: msg.get_from_id(),
+        viewtype: msg.get_viewtype(),
+        from_first_name: from.first_name,
+        from_profile_image: from.profile_image,
+        from_color: from.color,
+        starred: msg.is_starred(),
+        state: msg.get_state(),
+        text: msg.get_text(),
+        timestamp: OffsetDateTime::from_unix_timestamp(msg.get_sort_timestamp()),
+        is_info: msg.is_info(),
+        file,
+        file_width: msg.get_width(),
+        file_height: msg.get_height(),
+        file_bytes: msg.get_filebytes(context).await,
+        duration: msg.get_duration(),
+        poster,
+        error,
+        local_id: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_email_roundtrip() {
+        for email in &[
+            "alice@example.org",
+            "a.b+c_d-e@example.org",
+            ".hidden@example.org",
+            "a/b@example.org",
+            "..",
+            "percent%@example.org",
+            "ünïcödé@example.org",
+        ] {
+            let name = encode_email(email);
+            assert!(!name.contains('/'), "{}", name);
+            assert!(!name.starts_with('.'), "{}", name);
+            assert_eq!(decode_email(&name).as_deref(), Some(*email));
+        }
+    }
+
+    #[test]
+    fn test_encode_email() {
+        assert_eq!(encode_email("alice@example.org"), "alice@example.org");
+        assert_eq!(encode_email("a/b@example.org"), "a%2Fb@example.org");
+        assert_eq!(encode_email(".a@example.org"), "%2Ea@example.org");
+    }
+
+    #[test]
+    fn test_encode_email_ignores_case() {
+        assert_eq!(encode_email("Alice@Example.org"), "alice@example.org");
+        assert_eq!(
+            db_path_in(Path::new("dir"), "ALICE@example.org"),
+            db_path_in(Path::new("dir"), "alice@example.org")
+        );
+        assert_eq!(normalize_email(" Alice@Example.org "), "alice@example.org");
+    }
+
+    #[test]
+    fn test_decode_email_rejects_non_canonical() {
+        // uppercase letters
+        assert_eq!(decode_email("Alice@example.org"), None);
+        // lowercase hex
+        assert_eq!(decode_email("a%2fb@example.org"), None);
+        // characters that are always encoded
+        assert_eq!(decode_email("a b@example.org"), None);
+        assert_eq!(decode_email(".a@example.org"), None);
+        // needlessly encoded
+        assert_eq!(decode_email("%61@example.org"), None);
+        // truncated or invalid escapes
+        assert_eq!(decode_email("a%2"), None);
+        assert_eq!(decode_email("a%zz"), None);
+        // invalid utf-8
+        assert_eq!(decode_email("%FF"), None);
+    }
+
+    #[test]
+    fn test_security_flags() {
+        assert_eq!(Security::Automatic.imap_flags(), 0);
+        assert_eq!(Security::Automatic.smtp_flags(), 0);
+        assert_eq!(Security::Ssl.imap_flags(), DC_LP_IMAP_SOCKET_SSL);
+        assert_eq!(Security::StartTls.imap_flags(), DC_LP_IMAP_SOCKET_STARTTLS);
+        assert_eq!(Security::Plain.imap_flags(), DC_LP_IMAP_SOCKET_PLAIN);
+        assert_eq!(Security::Ssl.smtp_flags(), DC_LP_SMTP_SOCKET_SSL);
+        assert_eq!(Security::StartTls.smtp_flags(), DC_LP_SMTP_SOCKET_STARTTLS);
+        assert_eq!(Security::Plain.smtp_flags(), DC_LP_SMTP_SOCKET_PLAIN);
+
+        // both are combined into one config value
+        for imap in &Security::ALL {
+            for smtp in &Security::ALL {
+                assert_eq!(imap.imap_flags() & smtp.smtp_flags(), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_non_empty() {
+        assert_eq!(non_empty(""), None);
+        assert_eq!(non_empty("  "), None);
+        assert_eq!(non_empty(" imap.example.org "), Some("imap.example.org"));
+    }
+
+    #[test]
+    fn test_login_config() {
+        let imap = ServerSettings {
+            host: "imap.example.org".into(),
+            port: "993".into(),
+            security: Security::Ssl,
+            login: "alice".into(),
+        };
+        let smtp = ServerSettings {
+            host: " smtp.example.org ".into(),
+            port: "".into(),
+            security: Security::StartTls,
+            login: "".into(),
+        };
+
+        assert_eq!(
+            login_config("alice@example.org", "secret", &imap, &smtp),
+            vec![
+                (Config::Addr, Some("alice@example.org".into())),
+                (Config::MailPw, Some("secret".into())),
+                (Config::MailServer, Some("imap.example.org".into())),
+                (Config::MailPort, Some("993".into())),
+                (Config::MailUser, Some("alice".into())),
+                (Config::SendServer, Some("smtp.example.org".into())),
+                (Config::SendPort, None),
+                (Config::SendUser, None),
+                (
+                    Config::ServerFlags,
+                    Some((DC_LP_IMAP_SOCKET_SSL | DC_LP_SMTP_SOCKET_STARTTLS).to_string())
+                ),
+            ]
+        );
+
+        // without advanced settings everything is detected
+        let config = login_config(
+            "alice@example.org",
+            "secret",
+            &Default::default(),
+            &Default::default(),
+        );
+        assert!(config[2..].iter().all(|(_, value)| value.is_none()));
+    }
+
+    /// Stands in for an IMAP server that rejects every login.
+    ///
+    /// Returns its port and the users that tried to log in.
+    fn reject_imap_logins() -> (u16, std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+        use std::io::{BufRead, BufReader, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let users = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let seen = users.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => return,
+                };
+                let seen = seen.clone();
+                std::thread::spawn(move || {
+                    let reader = BufReader::new(stream.try_clone().unwrap());
+                    let _ = stream.write_all(b"* OK IMAP4rev1 ready\r\n");
+                    for line in reader.lines() {
+                        let line = match line {
+                            Ok(line) => line,
+                            Err(_) => return,
+                        };
+                        let mut parts = line.split_whitespace();
+                        let tag = parts.next().unwrap_or("*");
+                        let reply = match parts.next().map(|c| c.to_ascii_uppercase()).as_deref() {
+                            Some("CAPABILITY") => {
+                                format!("* CAPABILITY IMAP4rev1 AUTH=PLAIN\r\n{} OK done\r\n", tag)
+                            }
+                            Some("LOGIN") => {
+                                let user = parts.next().unwrap_or("").trim_matches('"');
+                                seen.lock().unwrap().push(user.to_string());
+                                format!("{} NO [AUTHENTICATIONFAILED] invalid\r\n", tag)
+                            }
+                            Some("LOGOUT") => format!("* BYE\r\n{} OK done\r\n", tag),
+                            _ => format!("{} BAD unknown command\r\n", tag),
+                        };
+                        if stream.write_all(reply.as_bytes()).is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+
+        (port, users)
+    }
+
+    #[test]
+    fn test_login_reports_rejected_credentials() {
+        let (port, users) = reject_imap_logins();
+        let dir = std::env::temp_dir().join(format!("delta-cool-login-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let imap = ServerSettings {
+            host: "127.0.0.1".into(),
+            port: port.to_string(),
+            security: Security::Plain,
+            login: "alice".into(),
+        };
+        // nothing listens here, the login already fails at IMAP
+        let smtp = ServerSettings {
+            host: "127.0.0.1".into(),
+            port: "1".into(),
+            security: Security::Plain,
+            login: "alice".into(),
+        };
+
+        async_std::task::block_on(async {
+            let mut account = Account::new_in(&dir, "alice@example.org").await.unwrap();
+            let res = async_std::future::timeout(
+                Duration::from_secs(60),
+                account.login("alice@example.org", "wrong", &imap, &smtp),
+            )
+            .await
+            .expect("login timed out");
+
+            assert!(res.is_err());
+            assert!(!account.logged_in().await);
+        });
+        assert_eq!(
+            users.lock().unwrap().first().map(String::as_str),
+            Some("alice")
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
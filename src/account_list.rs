@@ -0,0 +1,94 @@
+use iced::{button, Background, Color, Container, Element, HorizontalAlignment, Length, Row, Text};
+
+#[derive(Debug, Clone)]
+pub struct AccountEntry {
+    pub email: String,
+    pub fresh_msg_cnt: usize,
+    pub is_active: bool,
+    pub confirm_remove: bool,
+    select_state: button::State,
+    remove_state: button::State,
+}
+
+#[derive(Debug, Clone)]
+pub enum AccountEntryMessage {
+    Select,
+    Remove,
+    ConfirmRemove,
+}
+
+impl AccountEntry {
+    pub fn new(email: String, fresh_msg_cnt: usize, is_active: bool) -> Self {
+        Self {
+            email,
+            fresh_msg_cnt,
+            is_active,
+            confirm_remove: false,
+            select_state: Default::default(),
+            remove_state: Default::default(),
+        }
+    }
+
+    pub fn view(&mut self) -> Element<AccountEntryMessage> {
+        let mut row = Row::new().spacing(5).push(
+            Text::new(self.email.clone())
+                .color(if self.is_active {
+                    Color::BLACK
+                } else {
+                    Color::from_rgb(0.5, 0.5, 0.5)
+                })
+                .size(16)
+                .width(Length::Fill),
+        );
+
+        if self.fresh_msg_cnt > 0 {
+            row = row.push(badge(self.fresh_msg_cnt));
+        }
+
+        let remove = if self.confirm_remove {
+            button::Button::new(
+                &mut self.remove_state,
+                Text::new("Delete?").size(14).color([0.8, 0.0, 0.0]),
+            )
+            .on_press(AccountEntryMessage::ConfirmRemove)
+        } else {
+            button::Button::new(&mut self.remove_state, Text::new("×").size(14))
+                .on_press(AccountEntryMessage::Remove)
+        };
+
+        Row::new()
+            .spacing(5)
+            .push(
+                button::Button::new(&mut self.select_state, row)
+                    .width(Length::Fill)
+                    .on_press(AccountEntryMessage::Select),
+            )
+            .push(remove)
+            .into()
+    }
+}
+
+/// Unread counter shown next to accounts.
+pub fn badge<'a, Message: 'a>(count: usize) -> Element<'a, Message> {
+    struct Style {}
+    impl iced::container::StyleSheet for Style {
+        fn style(&self) -> iced::container::Style {
+            iced::container::Style {
+                background: Some(Background::Color(Color::from_rgb(0.2, 0.5, 0.9))),
+                border_radius: 10,
+                text_color: Some(Color::WHITE),
+                ..iced::container::Style::default()
+            }
+        }
+    }
+
+    Container::new(
+        Text::new(count.to_string())
+            .horizontal_alignment(HorizontalAlignment::Center)
+            .size(14),
+    )
+    .padding(3)
+    .width(Length::Units(28))
+    .style(Style {})
+    .into()
+}
@@ -0,0 +1,361 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use log::*;
+
+use crate::account::{self, Account, HOME_DIR};
+
+/// Marks `HOME_DIR` as using encoded database names, see `account::encode_email`.
+const ENCODED_NAMES_MARKER: &str = "encoded-names";
+
+/// All accounts that have a database in `HOME_DIR`, indexed by their email address.
+#[derive(Debug, Clone, Default)]
+pub struct Accounts {
+    accounts: BTreeMap<String, Account>,
+    active: Option<String>,
+}
+
+impl Accounts {
+    /// Opens every configured account found in `HOME_DIR`.
+    ///
+    /// Unconfigured ones are left over from failed logins and get deleted. Accounts that fail
+    /// to open are skipped, so the others can still be used.
+    pub async fn open() -> Result<Self> {
+        Self::open_in(&HOME_DIR).await
+    }
+
+    async fn open_in(dir: &Path) -> Result<Self> {
+        let mut accounts = Accounts::default();
+
+        remove_interrupted_imports(dir).await;
+        migrate_db_names(dir).await?;
+
+        for email in discover(dir)? {
+            let account = match Account::new_in(dir, &email).await {
+                Ok(account) => account,
+                Err(err) => {
+                    error!("failed to open account {}: {}", email, err);
+                    continue;
+                }
+            };
+            if account.logged_in().await {
+                accounts.add(email, account);
+            } else {
+                info!("deleting unconfigured account {}", email);
+                if let Err(err) = account.delete().await {
+                    warn!("failed to delete unconfigured account {}: {}", email, err);
+                }
+            }
+        }
+
+        Ok(accounts)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    /// Whether the address is registered, ignoring its case.
+    pub fn contains(&self, email: &str) -> bool {
+        self.accounts.contains_key(&account::normalize_email(email))
+    }
+
+    pub fn get(&self, email: &str) -> Option<&Account> {
+        self.accounts.get(email)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Account)> {
+        self.accounts.iter()
+    }
+
+    pub fn active_email(&self) -> Option<&str> {
+        self.active.as_deref()
+    }
+
+    pub fn active(&self) -> Option<&Account> {
+        self.active
+            .as_ref()
+            .and_then(|email| self.accounts.get(email))
+    }
+
+    pub fn is_active(&self, email: &str) -> bool {
+        self.active.as_deref() == Some(email)
+    }
+
+    /// Makes the given account the active one, returns `false` if it is unknown.
+    pub fn select(&mut self, email: &str) -> bool {
+        if !self.accounts.contains_key(email) {
+            return false;
+        }
+
+        self.active = Some(email.to_string());
+        true
+    }
+
+    /// Adds an account, it becomes the active one if there was none before.
+    pub fn add(&mut self, email: String, account: Account) {
+        if self.active.is_none() {
+            self.active = Some(email.clone());
+        }
+        self.accounts.insert(email, account);
+    }
+
+    /// Removes the account from the registry, its data is deleted with `Account::delete`.
+    pub fn remove(&mut self, email: &str) -> Option<Account> {
+        let account = self.accounts.remove(email)?;
+        if self.is_active(email) {
+            self.active = self.accounts.keys().next().cloned();
+        }
+
+        Some(account)
+    }
+}
+
+/// Email addresses of all accounts that have a database in `dir`.
+fn discover(dir: &Path) -> Result<Vec<String>> {
+    let pattern = dir.join("*.sqlite");
+
+    let mut emails = Vec::new();
+    for path in glob::glob(&pattern.to_string_lossy())? {
+        let path = path?;
+        match path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(account::decode_email)
+        {
+            Some(email) => emails.push(email),
+            None => warn!("ignoring unknown database {}", path.display()),
+        }
+    }
+
+    Ok(emails)
+}
+
+/// Deletes the temporary accounts of imports that were interrupted, e.g. by quitting.
+///
+/// Runs before the accounts are opened, so no import can be running yet.
+async fn remove_interrupted_imports(dir: &Path) {
+    let import_dir = account::import_dir_in(dir);
+    if !import_dir.exists() {
+        return;
+    }
+
+    info!("deleting interrupted imports in {}", import_dir.display());
+    if let Err(err) = async_std::fs::remove_dir_all(&import_dir).await {
+        warn!("failed to delete {}: {}", import_dir.display(), err);
+    }
+}
+
+/// Renames databases in `dir` from before names were encoded, named `<email>.sqlite`.
+///
+/// Only runs until all databases were moved, as afterwards encoded names can not be told
+/// apart from plain ones.
+async fn migrate_db_names(dir: &Path) -> Result<()> {
+    let marker = dir.join(ENCODED_NAMES_MARKER);
+    if marker.exists() {
+        return Ok(());
+    }
+
+    let mut failed = false;
+    // Addresses containing `/` ended up in sub directories.
+    let pattern = dir.join("**").join("*.sqlite");
+    for path in glob::glob(&pattern.to_string_lossy())? {
+        let path = path?;
+        let relative = match path.strip_prefix(dir) {
+            Ok(relative) => relative.with_extension(""),
+            Err(_) => continue,
+        };
+
+        // Skip files that were sent as attachments.
+        let in_blobdir = relative.parent().into_iter().any(|parent| {
+            parent
+                .components()
+                .any(|c| c.as_os_str().to_string_lossy().ends_with("-blobs"))
+        });
+        if in_blobdir {
+            continue;
+        }
+
+        let email = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let target = account::db_path_in(dir, &email);
+        if target != path {
+            if let Err(err) = migrate_db(&path, &target).await {
+                warn!("failed to migrate {}: {}", path.display(), err);
+                failed = true;
+            }
+        }
+    }
+
+    // Try again on the next start, the marker would hide the remaining databases.
+    if failed {
+        return Ok(());
+    }
+
+    async_std::fs::create_dir_all(dir).await?;
+    async_std::fs::write(&marker, b"").await?;
+
+    Ok(())
+}
+
+/// Moves a database to its encoded name.
+///
+/// Names that only change their case go through a temporary name, as on case insensitive
+/// filesystems the target already exists.
+async fn migrate_db(from: &Path, to: &Path) -> Result<()> {
+    if from.to_string_lossy().to_lowercase() != to.to_string_lossy().to_lowercase() {
+        return account::move_db(from, to).await;
+    }
+
+    let mut tmp = to.as_os_str().to_owned();
+    tmp.push(".migrating");
+    let tmp = PathBuf::from(tmp);
+    account::move_db(from, &tmp).await?;
+    account::move_db(&tmp, to).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory that is removed again when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("delta-cool-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_migrate_db_names() {
+        let dir = TempDir::new("migrate");
+        let dir = &dir.0;
+
+        std::fs::create_dir_all(dir.join("a")).unwrap();
+        std::fs::write(dir.join("a").join("b@example.org.sqlite"), b"db").unwrap();
+        std::fs::create_dir_all(dir.join("a").join("b@example.org.sqlite-blobs")).unwrap();
+        std::fs::write(dir.join(".c@example.org.sqlite"), b"db").unwrap();
+        std::fs::write(dir.join(".c@example.org.sqlite-wal"), b"wal").unwrap();
+        std::fs::write(dir.join("plain@example.org.sqlite"), b"db").unwrap();
+        std::fs::write(dir.join("Upper@Example.org.sqlite"), b"db").unwrap();
+        // attachments are not databases
+        std::fs::create_dir_all(dir.join("plain@example.org.sqlite-blobs")).unwrap();
+        std::fs::write(
+            dir.join("plain@example.org.sqlite-blobs").join("x.sqlite"),
+            b"blob",
+        )
+        .unwrap();
+
+        async_std::task::block_on(migrate_db_names(dir)).unwrap();
+
+        assert!(dir.join("a%2Fb@example.org.sqlite").exists());
+        assert!(dir.join("a%2Fb@example.org.sqlite-blobs").is_dir());
+        assert!(!dir.join("a").join("b@example.org.sqlite").exists());
+        assert!(dir.join("%2Ec@example.org.sqlite").exists());
+        assert!(dir.join("%2Ec@example.org.sqlite-wal").exists());
+        assert!(dir.join("plain@example.org.sqlite").exists());
+        let names = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        assert!(names.contains(&"upper@example.org.sqlite".to_string()));
+        assert!(!names.contains(&"Upper@Example.org.sqlite".to_string()));
+        assert!(dir
+            .join("plain@example.org.sqlite-blobs")
+            .join("x.sqlite")
+            .exists());
+        assert!(dir.join(ENCODED_NAMES_MARKER).exists());
+    }
+
+    #[test]
+    fn test_migrate_db_names_keeps_marker_unset_on_failure() {
+        let dir = TempDir::new("migrate-failure");
+        let dir = &dir.0;
+
+        std::fs::write(dir.join(".c@example.org.sqlite"), b"old").unwrap();
+        std::fs::write(dir.join(".c@example.org.sqlite-wal"), b"old").unwrap();
+        // the target of the journal is taken, so nothing may be moved
+        std::fs::write(dir.join("%2Ec@example.org.sqlite-wal"), b"taken").unwrap();
+
+        async_std::task::block_on(migrate_db_names(dir)).unwrap();
+
+        assert!(dir.join(".c@example.org.sqlite").exists());
+        assert!(dir.join(".c@example.org.sqlite-wal").exists());
+        assert!(!dir.join("%2Ec@example.org.sqlite").exists());
+        assert!(!dir.join(ENCODED_NAMES_MARKER).exists());
+    }
+
+    #[test]
+    fn test_open_skips_broken_accounts() {
+        let dir = TempDir::new("open-broken");
+        let dir = &dir.0;
+
+        std::fs::write(dir.join(ENCODED_NAMES_MARKER), b"").unwrap();
+        std::fs::write(dir.join("broken@example.org.sqlite"), b"not a database").unwrap();
+
+        let accounts = async_std::task::block_on(Accounts::open_in(dir)).unwrap();
+        assert!(accounts.is_empty());
+    }
+
+    #[test]
+    fn test_open_removes_interrupted_imports() {
+        let dir = TempDir::new("open-imports");
+        let dir = &dir.0;
+
+        let import_dir = account::import_dir_in(dir);
+        std::fs::create_dir_all(import_dir.join("1-2-3.sqlite-blobs")).unwrap();
+        std::fs::write(import_dir.join("1-2-3.sqlite"), b"db").unwrap();
+
+        async_std::task::block_on(Accounts::open_in(dir)).unwrap();
+        assert!(!import_dir.exists());
+    }
+
+    #[test]
+    fn test_open_ignores_case() {
+        let dir = TempDir::new("open-case");
+        let dir = &dir.0;
+
+        async_std::task::block_on(async {
+            let lower = Account::new_in(dir, "alice@example.org").await.unwrap();
+            let upper = Account::new_in(dir, "Alice@Example.org").await.unwrap();
+            assert_eq!(lower.context.get_dbfile(), upper.context.get_dbfile());
+
+            let mut accounts = Accounts::default();
+            accounts.add("alice@example.org".into(), lower);
+            assert!(accounts.contains("Alice@Example.org"));
+            assert!(accounts.contains(" ALICE@example.org"));
+        });
+    }
+
+    #[test]
+    fn test_move_db_keeps_existing_targets() {
+        let dir = TempDir::new("move-db");
+        let dir = &dir.0;
+
+        let from = dir.join("from.sqlite");
+        let to = dir.join("to.sqlite");
+        std::fs::write(&from, b"db").unwrap();
+        std::fs::write(dir.join("from.sqlite-wal"), b"wal").unwrap();
+        std::fs::write(dir.join("to.sqlite-wal"), b"taken").unwrap();
+
+        assert!(async_std::task::block_on(account::move_db(&from, &to)).is_err());
+        assert!(from.exists());
+        assert!(!to.exists());
+        assert_eq!(std::fs::read(dir.join("to.sqlite-wal")).unwrap(), b"taken");
+    }
+}
@@ -1,35 +1,347 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use deltachat::constants::Viewtype;
+use deltachat::message::MessageState;
 use iced::{
-    scrollable, Application, Color, Column, Command, Container, Element, HorizontalAlignment,
-    Length, Row, Scrollable, Subscription, Text,
+    button, scrollable, Align, Application, Color, Column, Command, Container, Element,
+    HorizontalAlignment, Length, Radio, Row, Scrollable, Subscription, Text,
 };
-use log::{error, info};
+use log::{error, info, warn};
 
-use crate::account::Account;
+use crate::account::{self, Account, ChatListFilter, ChatMessage, ChatState, ServerSettings};
+use crate::account_list::*;
+use crate::accounts::Accounts;
+use crate::avatar;
+use crate::backup::*;
 use crate::chat::*;
 use crate::chat_list::*;
+use crate::composer::*;
+use crate::dialog;
+use crate::format::{self, TimeFormat};
+use crate::media::{self, Attachment, Pasted};
+use crate::setup::*;
+
+/// How long a button has to be held down to open its context menu.
+const LONG_PRESS: Duration = Duration::from_millis(500);
+/// How long typing has to pause before the chats are searched.
+const SEARCH_DELAY: Duration = Duration::from_millis(300);
 
 #[derive(Debug)]
 pub enum App {
     Loading,
+    /// The accounts could not be opened
+    Failed(String),
+    Setup(Setup),
     Loaded(State),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct State {
-    account: Account,
+    accounts: Accounts,
+    account_list: Vec<AccountEntry>,
+    add_account_state: button::State,
+    /// Setup of an additional account
+    setup: Option<Setup>,
+    backup: Backup,
     scroll_chat: scrollable::State,
     scroll_chat_list: scrollable::State,
+    chat_search: ChatSearch,
+    contact_requests: Vec<ContactRequestEntry>,
+    chat_list: Vec<ChatListEntry>,
+    search_results: Vec<SearchResultEntry>,
+    chat: Vec<ChatMsg>,
+    chat_paging: ChatPaging,
+    selected_chat: Option<ChatState>,
+    composer: Composer,
+    time_format: TimeFormat,
+    /// Full size media of a message, shown instead of the chat
+    viewer: Option<MediaViewer>,
+    /// Chats to forward a message to, shown instead of the chat
+    forward: Option<ForwardPicker>,
+    /// Info about a message, shown instead of the chat
+    msg_info: Option<MessageInfo>,
+    /// When the left mouse button was last pressed, to detect long presses
+    mouse_pressed_at: Option<Instant>,
+    /// Chat a picked or dropped file is being loaded for, to attach it there
+    loading_attachment: Option<deltachat::chat::ChatId>,
+    /// Chat the clipboard is being read for, to paste it there
+    pasting: Option<deltachat::chat::ChatId>,
+    /// Failure of something the user started, shown until dismissed
+    error: Option<String>,
+    dismiss_error_state: button::State,
+}
+
+/// View models rebuilt from the account state.
+#[derive(Debug, Clone, Default)]
+pub struct View {
+    account_list: Vec<AccountEntry>,
+    /// The account the chat views belong to
+    active: Option<String>,
+    chat_filter: ChatListFilter,
+    contact_requests: Vec<ContactRequestEntry>,
     chat_list: Vec<ChatListEntry>,
+    search_results: Vec<SearchResultEntry>,
     chat: Vec<ChatMsg>,
-    chat_name: String,
+    chat_paging: ChatPaging,
+    selected_chat: Option<ChatState>,
 }
 
 #[derive(Debug, Clone)]
 pub enum Message {
-    Loaded(std::result::Result<State, String>),
-    Event(deltachat::Event),
+    Loaded(std::result::Result<Option<Accounts>, String>),
+    Setup(SetupMessage),
+    AccountCreated(std::result::Result<Account, String>),
+    LoggedIn(std::result::Result<(String, Account), String>),
+    AccountEntryMessage(String, AccountEntryMessage),
+    AddAccount,
+    AccountRemoved(std::result::Result<(), String>),
+    BackupPicked(Option<PathBuf>),
+    ImportAccountCreated(std::result::Result<(Account, PathBuf), String>),
+    Imported(std::result::Result<String, String>),
+    Backup(BackupMessage),
+    ExportDirPicked(Option<PathBuf>),
+    Exported(std::result::Result<(), String>),
+    Event(String, deltachat::Event),
     ChatListEntryMessage(deltachat::chat::ChatId, ChatListEntryMessage),
+    ChatSearch(ChatSearchMessage),
+    /// Typing the query with the given `ChatSearch::query_generation` paused
+    SearchDebounced(u64),
+    ContactRequest(u32, ContactRequestMessage),
+    SearchResultEntryMessage(deltachat::message::MsgId, SearchResultEntryMessage),
     ChatMessage(deltachat::message::MsgId, ChatMsgMessage),
+    MediaViewer(MediaViewerMessage),
+    FileSaved(std::result::Result<(), String>),
+    Copied(std::result::Result<(), String>),
+    Opened(std::result::Result<(), String>),
+    DismissError,
+    /// Chats the message can be forwarded to were loaded
+    ForwardTargetsLoaded(
+        deltachat::message::MsgId,
+        std::result::Result<Vec<(deltachat::chat::ChatId, String)>, String>,
+    ),
+    ForwardPicker(ForwardPickerMessage),
+    MessageInfoLoaded(String),
+    MessageInfo(MessageInfoMessage),
+    ChatPaging(ChatPagingMessage),
+    Composer(ComposerMessage),
+    AttachmentPicked(Option<PathBuf>),
+    AttachmentLoaded(std::result::Result<Attachment, String>),
+    Pasted(std::result::Result<Option<Pasted>, String>),
+    /// Result of sending the pending message with the given local id
+    Sent(u64, std::result::Result<deltachat::message::MsgId, String>),
+    Retried(deltachat::message::MsgId, std::result::Result<(), String>),
+    Refreshed(std::result::Result<Option<View>, String>),
+    /// A poster was generated for the video of the message
+    PosterLoaded(deltachat::message::MsgId, Option<PathBuf>),
+    /// A profile image was rounded, so it can be shown
+    AvatarRounded,
+    NativeEvent(iced_native::Event),
+    TimeFormatChanged(TimeFormat),
+    TimeFormatSaved(std::result::Result<(), String>),
+    /// Relative times need to be updated every now and then.
+    Tick,
+}
+
+impl State {
+    fn new(accounts: Accounts, view: View) -> Self {
+        State {
+            accounts,
+            account_list: view.account_list,
+            add_account_state: Default::default(),
+            setup: None,
+            backup: Default::default(),
+            scroll_chat: Default::default(),
+            scroll_chat_list: Default::default(),
+            chat_search: Default::default(),
+            contact_requests: view.contact_requests,
+            chat_list: view.chat_list,
+            search_results: view.search_results,
+            chat: view.chat,
+            chat_paging: view.chat_paging,
+            selected_chat: view.selected_chat,
+            composer: Default::default(),
+            viewer: None,
+            forward: None,
+            msg_info: None,
+            time_format: TimeFormat::load(),
+            mouse_pressed_at: None,
+            loading_attachment: None,
+            pasting: None,
+            error: None,
+            dismiss_error_state: Default::default(),
+        }
+    }
+
+    fn apply_view(&mut self, view: View) {
+        self.account_list = view.account_list;
+
+        // The active account might have changed while the view was loading.
+        if view.active.as_deref() == self.accounts.active_email() {
+            let same_chat = match (&self.selected_chat, &view.selected_chat) {
+                (Some(old), Some(new)) => old.id == new.id,
+                _ => false,
+            };
+            if !same_chat {
+                // Drafts belong to the chat they were written in.
+                self.composer.reset();
+                self.viewer = None;
+                self.forward = None;
+                self.msg_info = None;
+            }
+
+            // The filter is edited here, only take it over when the account changed.
+            if self.chat_search.email != view.active {
+                self.chat_search.email = view.active.clone();
+                self.chat_search.filter = view.chat_filter;
+            }
+
+            self.contact_requests = view.contact_requests;
+            apply_diff(&mut self.chat_list, view.chat_list);
+            self.search_results = view.search_results;
+
+            // Keep what the user expanded on messages that are still shown.
+            let mut chat = view.chat;
+            for entry in chat.iter_mut().filter(|entry| !entry.msg.id.is_unset()) {
+                if let Some(old) = self.chat.iter().find(|old| old.msg.id == entry.msg.id) {
+                    entry.show_error = old.show_error;
+                    entry.show_timestamp = old.show_timestamp;
+                    entry.show_actions = old.show_actions;
+                    entry.confirm_delete = old.confirm_delete;
+                }
+            }
+            // Messages that are still being sent might not be loaded yet.
+            if same_chat {
+                let pending = self
+                    .chat
+                    .drain(..)
+                    .filter(|entry| entry.msg.local_id.is_some())
+                    .filter(|entry| {
+                        entry.msg.id.is_unset()
+                            || !chat.iter().any(|new| new.msg.id == entry.msg.id)
+                    })
+                    .collect::<Vec<_>>();
+                chat.extend(pending);
+            }
+            self.chat = chat;
+            self.chat_paging = view.chat_paging;
+            self.selected_chat = view.selected_chat;
+        }
+    }
+
+    /// Generates the missing posters of shown videos, which runs `ffmpeg` and takes a while.
+    fn load_posters(&self) -> Command<Message> {
+        let commands = self
+            .chat
+            .iter()
+            .filter(|entry| entry.msg.viewtype == Viewtype::Video && entry.msg.poster.is_none())
+            .filter_map(|entry| Some((entry.msg.id, entry.msg.file.clone()?)))
+            .filter(|(_, file)| media::needs_video_poster(file))
+            .map(|(id, file)| {
+                Command::perform(media::video_poster(file), move |poster| {
+                    Message::PosterLoaded(id, poster)
+                })
+            })
+            .collect();
+
+        Command::batch(commands)
+    }
+
+    /// Rounds the profile images that are shown, `avatar::view` leaves them out until then.
+    fn round_avatars(&self) -> Command<Message> {
+        let images = self
+            .chat_list
+            .iter()
+            .map(|entry| &entry.profile_image)
+            .chain(self.chat.iter().map(|entry| &entry.msg.from_profile_image))
+            .chain(
+                self.contact_requests
+                    .iter()
+                    .map(|entry| &entry.request.profile_image),
+            )
+            .chain(self.selected_chat.iter().map(|chat| &chat.profile_image))
+            .flatten()
+            .filter(|image| avatar::needs_round(image))
+            .cloned()
+            .collect::<std::collections::BTreeSet<_>>();
+
+        Command::batch(
+            images
+                .into_iter()
+                .map(|image| Command::perform(avatar::round(image), |_| Message::AvatarRounded)),
+        )
+    }
+
+    /// Runs an action picked from the menu of a message.
+    fn message_action(
+        &mut self,
+        msg_id: deltachat::message::MsgId,
+        action: MessageAction,
+    ) -> Command<Message> {
+        match action {
+            MessageAction::Reply => {
+                let quote = self
+                    .chat
+                    .iter()
+                    .find(|entry| entry.msg.id == msg_id)
+                    .and_then(|entry| {
+                        entry
+                            .msg
+                            .text
+                            .clone()
+                            .filter(|text| !text.is_empty())
+                            .or_else(|| {
+                                let file = entry.msg.file.as_ref()?.file_name()?;
+                                Some(file.to_string_lossy().to_string())
+                            })
+                    });
+                if let Some(quote) = quote {
+                    self.composer.quote(&quote);
+                }
+                Command::none()
+            }
+            MessageAction::Forward => match self.accounts.active() {
+                Some(account) => {
+                    let account = account.clone();
+                    Command::perform(
+                        async move {
+                            account
+                                .forward_targets()
+                                .await
+                                .map_err(|err| err.to_string())
+                        },
+                        move |targets| Message::ForwardTargetsLoaded(msg_id, targets),
+                    )
+                }
+                None => Command::none(),
+            },
+            MessageAction::CopyText => {
+                let text = self
+                    .chat
+                    .iter()
+                    .find(|entry| entry.msg.id == msg_id)
+                    .and_then(|entry| entry.msg.text.clone());
+                match text {
+                    Some(text) => Command::perform(media::copy_text(text), Message::Copied),
+                    None => Command::none(),
+                }
+            }
+            MessageAction::Info => match self.accounts.active() {
+                Some(account) => {
+                    let account = account.clone();
+                    Command::perform(
+                        async move { account.message_info(msg_id).await },
+                        Message::MessageInfoLoaded,
+                    )
+                }
+                None => Command::none(),
+            },
+            MessageAction::Star(_) | MessageAction::Delete => Command::perform(
+                message_action(self.accounts.clone(), msg_id, action),
+                Message::Refreshed,
+            ),
+        }
+    }
 }
 
 impl Application for App {
@@ -51,136 +363,985 @@ impl Application for App {
     fn subscription(&self) -> Subscription<Self::Message> {
         match self {
             App::Loaded(state) => {
-                Subscription::from_recipe(EventSubscription::new(state.account.get_event_emitter()))
-                    .map(Message::Event)
+                let mut subscriptions = state
+                    .accounts
+                    .iter()
+                    .map(|(email, account)| event_subscription(email, account))
+                    .collect::<Vec<_>>();
+                if let Some(setup) = &state.setup {
+                    if let Some(account) = &setup.account {
+                        subscriptions.push(event_subscription(&setup.events_id(), account));
+                    }
+                }
+                subscriptions.push(iced_native::subscription::events().map(Message::NativeEvent));
+                subscriptions.push(
+                    Subscription::from_recipe(Every(Duration::from_secs(60)))
+                        .map(|_| Message::Tick),
+                );
+
+                Subscription::batch(subscriptions)
             }
-            _ => Subscription::none(),
+            App::Setup(setup) => match &setup.account {
+                Some(account) => event_subscription(&setup.events_id(), account),
+                None => Subscription::none(),
+            },
+            App::Loading | App::Failed(_) => Subscription::none(),
         }
     }
 
     fn update(&mut self, message: Self::Message) -> Command<Self::Message> {
         match message {
-            Message::Loaded(Ok(state)) => {
-                *self = App::Loaded(state);
+            Message::Loaded(Ok(Some(accounts))) => {
+                return self.load(accounts);
+            }
+            Message::Loaded(Ok(None)) => {
+                *self = App::Setup(Setup::default());
+            }
+            Message::Setup(SetupMessage::Cancel) => {
+                if let App::Loaded(state) = self {
+                    if let Some(account) = state.setup.take().and_then(|setup| setup.account) {
+                        return Command::perform(
+                            async move { account.delete().await.map_err(|err| err.to_string()) },
+                            Message::AccountRemoved,
+                        );
+                    }
+                }
+            }
+            Message::Setup(SetupMessage::ImportBackup) => {
+                return Command::perform(
+                    dialog::pick_file("Import backup", Some((&["*.tar"][..], "Backup files"))),
+                    Message::BackupPicked,
+                );
+            }
+            Message::BackupPicked(Some(path)) => {
+                if let Some(setup) = self.setup_mut() {
+                    setup.start_import();
+                    let failed = setup.account.take();
+                    return Command::perform(
+                        async move {
+                            if let Some(failed) = failed {
+                                if let Err(err) = failed.delete().await {
+                                    warn!("failed to delete unconfigured account: {}", err);
+                                }
+                            }
+                            Account::new_for_import()
+                                .await
+                                .map(|account| (account, path))
+                                .map_err(|err| err.to_string())
+                        },
+                        Message::ImportAccountCreated,
+                    );
+                }
+            }
+            Message::BackupPicked(None) => {}
+            Message::ImportAccountCreated(Ok((account, path))) => {
+                if let Some(setup) = self.setup_mut() {
+                    setup.account = Some(account.clone());
+                    return Command::perform(
+                        async move { account.import(&path).await.map_err(|err| err.to_string()) },
+                        Message::Imported,
+                    );
+                }
+            }
+            Message::Imported(result) => {
+                // Taking the account out of the setup also ends its event subscription, so
+                // nothing else holds it while it is moved or deleted.
+                if let Some(setup) = self.setup_mut() {
+                    if let Some(account) = setup.account.take() {
+                        match result {
+                            Ok(email) => {
+                                return Command::perform(
+                                    async move {
+                                        account
+                                            .finish_import(email)
+                                            .await
+                                            .map_err(|err| err.to_string())
+                                    },
+                                    Message::LoggedIn,
+                                );
+                            }
+                            Err(err) => {
+                                error!("{}", err);
+                                setup.fail(err);
+                                return Command::perform(
+                                    async move { account.delete().await.map_err(|err| err.to_string()) },
+                                    Message::AccountRemoved,
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+            Message::Setup(msg) => {
+                let (setup, accounts) = match self {
+                    App::Setup(setup) => (setup, None),
+                    App::Loaded(State {
+                        setup: Some(setup),
+                        accounts,
+                        ..
+                    }) => (setup, Some(accounts)),
+                    _ => return Command::none(),
+                };
+
+                if setup.update(msg) {
+                    let email = account::normalize_email(&setup.email);
+                    if accounts.map(|a| a.contains(&email)).unwrap_or_default() {
+                        setup.progress = None;
+                        setup.error = Some(format!("{} is already logged in", email));
+                        return Command::none();
+                    }
+
+                    // Start over with a fresh account for the entered address, the one of a
+                    // failed attempt is left unconfigured and has to go first.
+                    let failed = setup.account.take();
+                    return Command::perform(
+                        async move {
+                            if let Some(failed) = failed {
+                                if let Err(err) = failed.delete().await {
+                                    warn!("failed to delete unconfigured account: {}", err);
+                                }
+                            }
+                            Account::new(&email).await.map_err(|err| err.to_string())
+                        },
+                        Message::AccountCreated,
+                    );
+                }
+            }
+            Message::AccountCreated(Ok(account)) => {
+                if let Some(setup) = self.setup_mut() {
+                    setup.account = Some(account.clone());
+                    return Command::perform(
+                        login(
+                            account,
+                            account::normalize_email(&setup.email),
+                            setup.password.clone(),
+                            setup.imap.settings.clone(),
+                            setup.smtp.settings.clone(),
+                        ),
+                        Message::LoggedIn,
+                    );
+                }
+            }
+            Message::AccountCreated(Err(err))
+            | Message::ImportAccountCreated(Err(err))
+            | Message::LoggedIn(Err(err)) => {
+                error!("{}", err);
+                if let Some(setup) = self.setup_mut() {
+                    setup.fail(err);
+                }
             }
-            Message::Event(ev) => {
-                info!("{:?}", ev);
+            Message::LoggedIn(Ok((email, account))) => match self {
+                App::Loaded(state) => {
+                    state.setup = None;
+                    state.accounts.add(email.clone(), account);
+                    state.accounts.select(&email);
+                    state.chat = Default::default();
+                    state.selected_chat = None;
+                    return refresh(&state.accounts);
+                }
+                _ => {
+                    let mut accounts = Accounts::default();
+                    accounts.add(email, account);
+                    return self.load(accounts);
+                }
+            },
+            Message::AddAccount => {
+                if let App::Loaded(state) = self {
+                    state.setup = Some(Setup::cancelable());
+                }
+            }
+            Message::AccountEntryMessage(email, msg) => {
+                if let App::Loaded(state) = self {
+                    match msg {
+                        AccountEntryMessage::Select => {
+                            if state.accounts.select(&email) {
+                                state.chat = Default::default();
+                                state.selected_chat = None;
+                                return refresh(&state.accounts);
+                            }
+                        }
+                        AccountEntryMessage::Remove => {
+                            for entry in state.account_list.iter_mut() {
+                                entry.confirm_remove = entry.email == email;
+                            }
+                        }
+                        AccountEntryMessage::ConfirmRemove => {
+                            if let Some(account) = state.accounts.remove(&email) {
+                                let delete = Command::perform(
+                                    async move { account.delete().await.map_err(|err| err.to_string()) },
+                                    Message::AccountRemoved,
+                                );
+
+                                if state.accounts.is_empty() {
+                                    *self = App::Setup(Setup::default());
+                                    return delete;
+                                }
+
+                                state.chat = Default::default();
+                                state.selected_chat = None;
+                                return Command::batch(vec![delete, refresh(&state.accounts)]);
+                            }
+                        }
+                    }
+                }
+            }
+            Message::Backup(BackupMessage::Export) => {
+                return Command::perform(
+                    dialog::pick_folder("Export backup to"),
+                    Message::ExportDirPicked,
+                );
+            }
+            Message::ExportDirPicked(Some(dir)) => {
+                if let App::Loaded(State {
+                    accounts, backup, ..
+                }) = self
+                {
+                    if let (Some(email), Some(account)) =
+                        (accounts.active_email(), accounts.active())
+                    {
+                        backup.start(email.to_string());
+                        let account = account.clone();
+                        return Command::perform(
+                            async move { account.export(&dir).await.map_err(|err| err.to_string()) },
+                            Message::Exported,
+                        );
+                    }
+                }
+            }
+            Message::ExportDirPicked(None) => {}
+            Message::Exported(result) => {
+                if let Err(err) = &result {
+                    error!("{}", err);
+                }
+                if let App::Loaded(State { backup, .. }) = self {
+                    backup.finish(result);
+                }
+            }
+            Message::AccountRemoved(Ok(())) => {}
+            Message::AccountRemoved(Err(err)) => {
+                error!("failed to remove account: {}", err);
+            }
+            Message::Event(email, ev) => {
+                info!("{}: {:?}", email, ev);
+                match &ev {
+                    deltachat::Event::ConfigureProgress(progress)
+                    | deltachat::Event::ImexProgress(progress) => {
+                        if let Some(setup) = self.setup_mut() {
+                            // 0 signals an error, which is reported by `login` or `import` itself.
+                            if *progress > 0 && setup.is_configuring() && setup.events_id() == email
+                            {
+                                setup.progress = Some(*progress);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+                if let App::Loaded(State { backup, .. }) = self {
+                    if backup.is_running(&email) {
+                        match &ev {
+                            deltachat::Event::ImexProgress(progress) if *progress > 0 => {
+                                backup.progress = Some(*progress);
+                            }
+                            deltachat::Event::ImexFileWritten(path) => {
+                                backup.written = Some(path.clone());
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                if let App::Loaded(State { accounts, .. }) = self {
+                    return Command::perform(
+                        handle_event(accounts.clone(), email, ev),
+                        Message::Refreshed,
+                    );
+                }
             }
             Message::ChatListEntryMessage(id, msg) => match msg {
+                ChatListEntryMessage::Select if id.is_archived_link() => {
+                    return self.update(Message::ChatSearch(ChatSearchMessage::Archived(true)));
+                }
                 ChatListEntryMessage::Select => {
-                    if let App::Loaded(State { account, .. }) = self {
-                        let mut account = account.clone();
-                        let id = id.clone();
+                    if self.take_long_press() {
+                        // Long pressing opens the context menu instead.
+                        return self.update(Message::ChatListEntryMessage(
+                            id,
+                            ChatListEntryMessage::ToggleActions,
+                        ));
+                    }
+                    if let App::Loaded(State { accounts, .. }) = self {
                         return Command::perform(
-                            async move {
-                                account.select_chat(id).await.unwrap();
-                                account.load_message_list().await.unwrap();
-                                let chat = account
-                                    .state
-                                    .read()
-                                    .await
-                                    .chat_msgs
-                                    .iter()
-                                    .map(|(_, msg)| ChatMsg::new(msg.clone()))
-                                    .collect::<Vec<_>>();
-
-                                (
-                                    chat,
-                                    account
-                                        .state
-                                        .read()
-                                        .await
-                                        .selected_chat
-                                        .as_ref()
-                                        .map(|s| s.name.clone())
-                                        .unwrap_or_default(),
-                                )
-                            },
-                            move |(chat, name)| {
-                                Message::ChatListEntryMessage(
-                                    id,
-                                    ChatListEntryMessage::Selected(chat, name),
-                                )
-                            },
+                            select_chat(accounts.clone(), id),
+                            Message::Refreshed,
                         );
                     }
                 }
-                ChatListEntryMessage::Selected(new_chat, new_chat_name) => {
-                    info!("selected chat: {}", id);
+                msg => {
                     if let App::Loaded(State {
-                        chat, chat_name, ..
+                        accounts,
+                        chat_list,
+                        ..
                     }) = self
                     {
-                        *chat = new_chat;
-                        *chat_name = new_chat_name;
+                        let action = chat_list
+                            .iter_mut()
+                            .find(|entry| entry.id == id)
+                            .and_then(|entry| entry.update(msg));
+                        if let Some(action) = action {
+                            return Command::perform(
+                                chat_action(accounts.clone(), id, action),
+                                Message::Refreshed,
+                            );
+                        }
+                    }
+                }
+            },
+            Message::ContactRequest(contact_id, msg) => {
+                if let App::Loaded(State {
+                    accounts,
+                    contact_requests,
+                    ..
+                }) = self
+                {
+                    // Accepting creates the chat from the newest message of the contact.
+                    let msg_id = contact_requests
+                        .iter()
+                        .find(|entry| entry.request.contact_id == contact_id)
+                        .map(|entry| entry.request.msg_id);
+                    if let Some(msg_id) = msg_id {
+                        return Command::perform(
+                            contact_request(accounts.clone(), msg_id, contact_id, msg),
+                            Message::Refreshed,
+                        );
+                    }
+                }
+            }
+            Message::ChatSearch(msg) => {
+                if let App::Loaded(State {
+                    accounts,
+                    chat_search,
+                    ..
+                }) = self
+                {
+                    let debounce = matches!(msg, ChatSearchMessage::QueryChanged(_));
+                    let filter = chat_search.update(msg);
+                    if debounce {
+                        let generation = chat_search.query_generation;
+                        return Command::perform(
+                            async move {
+                                async_std::task::sleep(SEARCH_DELAY).await;
+                                generation
+                            },
+                            Message::SearchDebounced,
+                        );
+                    }
+
+                    return Command::perform(
+                        set_chat_filter(accounts.clone(), filter),
+                        Message::Refreshed,
+                    );
+                }
+            }
+            Message::SearchDebounced(generation) => {
+                if let App::Loaded(State {
+                    accounts,
+                    chat_search,
+                    ..
+                }) = self
+                {
+                    if generation == chat_search.query_generation {
+                        return Command::perform(
+                            set_chat_filter(accounts.clone(), chat_search.filter.clone()),
+                            Message::Refreshed,
+                        );
+                    }
+                }
+            }
+            Message::SearchResultEntryMessage(id, msg) => match msg {
+                SearchResultEntryMessage::Select => {
+                    if let App::Loaded(State { accounts, .. }) = self {
+                        return Command::perform(
+                            jump_to_message(accounts.clone(), id),
+                            Message::Refreshed,
+                        );
+                    }
+                }
+            },
+            Message::ChatPaging(msg) => {
+                if let App::Loaded(State { accounts, .. }) = self {
+                    return Command::perform(load_page(accounts.clone(), msg), Message::Refreshed);
+                }
+            }
+            Message::ChatMessage(id, msg) => match msg {
+                ChatMsgMessage::OpenMedia => {
+                    if let App::Loaded(State { chat, viewer, .. }) = self {
+                        if let Some(entry) = chat.iter().find(|entry| entry.msg.id == id) {
+                            *viewer = Some(MediaViewer::new(entry.msg.clone()));
+                        }
+                    }
+                }
+                ChatMsgMessage::OpenFile => {
+                    if let Some(file) = self.message_file(id) {
+                        return Command::perform(crate::media::open(file), Message::Opened);
+                    }
+                }
+                ChatMsgMessage::SaveFile => {
+                    if let Some(file) = self.message_file(id) {
+                        return Command::perform(crate::media::save_as(file), Message::FileSaved);
+                    }
+                }
+                ChatMsgMessage::Retry => {
+                    if let App::Loaded(State { accounts, .. }) = self {
+                        if let Some(account) = accounts.active() {
+                            let account = account.clone();
+                            return Command::perform(
+                                async move {
+                                    account
+                                        .retry_message(id)
+                                        .await
+                                        .map_err(|err| err.to_string())
+                                },
+                                move |res| Message::Retried(id, res),
+                            );
+                        }
+                    }
+                }
+                msg => {
+                    if let App::Loaded(state) = self {
+                        let action = state
+                            .chat
+                            .iter_mut()
+                            .find(|entry| entry.msg.id == id)
+                            .and_then(|entry| entry.update(msg));
+                        if let Some(action) = action {
+                            return state.message_action(id, action);
+                        }
+                    }
+                }
+            },
+            Message::FileSaved(Ok(())) | Message::Copied(Ok(())) | Message::Opened(Ok(())) => {}
+            Message::FileSaved(Err(err))
+            | Message::Copied(Err(err))
+            | Message::Opened(Err(err)) => {
+                error!("{}", err);
+                if let App::Loaded(state) = self {
+                    state.error = Some(err);
+                }
+            }
+            Message::DismissError => {
+                if let App::Loaded(state) = self {
+                    state.error = None;
+                }
+            }
+            Message::ForwardTargetsLoaded(msg_id, Ok(chats)) => {
+                if let App::Loaded(State { forward, .. }) = self {
+                    *forward = Some(ForwardPicker::new(msg_id, chats));
+                }
+            }
+            Message::ForwardTargetsLoaded(_, Err(err)) => {
+                error!("{}", err);
+                if let App::Loaded(state) = self {
+                    state.error = Some(err);
+                }
+            }
+            Message::ForwardPicker(msg) => {
+                if let App::Loaded(State {
+                    accounts, forward, ..
+                }) = self
+                {
+                    if let (Some(picker), ForwardPickerMessage::Select(chat_id)) =
+                        (forward.take(), msg)
+                    {
+                        return Command::perform(
+                            forward_message(accounts.clone(), picker.msg_id, chat_id),
+                            Message::Refreshed,
+                        );
+                    }
+                }
+            }
+            Message::MessageInfoLoaded(info) => {
+                if let App::Loaded(State { msg_info, .. }) = self {
+                    *msg_info = Some(MessageInfo::new(info));
+                }
+            }
+            Message::MessageInfo(MessageInfoMessage::Close) => {
+                if let App::Loaded(State { msg_info, .. }) = self {
+                    *msg_info = None;
+                }
+            }
+            Message::MediaViewer(msg) => match msg {
+                MediaViewerMessage::Close => {
+                    if let App::Loaded(State { viewer, .. }) = self {
+                        *viewer = None;
                     }
                 }
             },
-            Message::ChatMessage(_, _) => {}
+            Message::Composer(ComposerMessage::Attach) => {
+                return Command::perform(
+                    dialog::pick_file("Attach file", None),
+                    Message::AttachmentPicked,
+                );
+            }
+            Message::Composer(msg) => {
+                if let App::Loaded(State {
+                    accounts,
+                    chat,
+                    chat_paging,
+                    composer,
+                    selected_chat,
+                    ..
+                }) = self
+                {
+                    let chat_id = selected_chat.as_ref().map(|chat| chat.id);
+                    if let (Some(outgoing), Some(account), Some(chat_id)) =
+                        (composer.update(msg), accounts.active(), chat_id)
+                    {
+                        // The sent message is not part of the loaded older ones, so show the
+                        // newest messages instead.
+                        if chat_paging.has_newer {
+                            let accounts = accounts.clone();
+                            let account = account.clone();
+                            return Command::perform(
+                                async move {
+                                    send(account.clone(), chat_id, outgoing).await?;
+                                    account
+                                        .load_newest_messages()
+                                        .await
+                                        .map_err(|err| err.to_string())?;
+                                    Ok(Some(load_view(&accounts).await))
+                                },
+                                Message::Refreshed,
+                            );
+                        }
+
+                        // Show the message right away, it gets replaced with the real one once
+                        // the core reports `MsgsChanged`.
+                        let pending = match &outgoing {
+                            Outgoing::Text(text) => ChatMessage::pending(text.clone()),
+                            Outgoing::File(attachment, caption) => {
+                                ChatMessage::pending_file(attachment, caption.clone())
+                            }
+                        };
+                        let local_id = pending.local_id.unwrap_or_default();
+                        chat.push(ChatMsg::new(pending));
+                        return Command::perform(
+                            send(account.clone(), chat_id, outgoing),
+                            move |res| Message::Sent(local_id, res),
+                        );
+                    }
+                }
+            }
+            Message::AttachmentPicked(Some(path)) => {
+                if let App::Loaded(state) = self {
+                    state.loading_attachment = state.selected_chat.as_ref().map(|chat| chat.id);
+                    return Command::perform(Attachment::load(path), Message::AttachmentLoaded);
+                }
+            }
+            Message::AttachmentPicked(None) => {}
+            Message::AttachmentLoaded(Ok(attachment)) => {
+                if let App::Loaded(state) = self {
+                    // The user might have switched to another chat in the meantime.
+                    let chat_id = state.selected_chat.as_ref().map(|chat| chat.id);
+                    let loading = state.loading_attachment.take();
+                    if loading.is_some() && loading == chat_id {
+                        state.composer.set_attachment(attachment);
+                    }
+                }
+            }
+            Message::AttachmentLoaded(Err(err)) => {
+                error!("{}", err);
+                if let App::Loaded(state) = self {
+                    state.loading_attachment = None;
+                    state.error = Some(err);
+                }
+            }
+            Message::Sent(local_id, Ok(msg_id)) => {
+                if let App::Loaded(State { chat, .. }) = self {
+                    // Drop the pending message if the real one is loaded already, otherwise
+                    // the next refresh that contains it replaces it.
+                    let loaded = chat.iter().any(|entry| entry.msg.id == msg_id);
+                    if loaded {
+                        chat.retain(|entry| entry.msg.local_id != Some(local_id));
+                    } else if let Some(entry) = chat
+                        .iter_mut()
+                        .find(|entry| entry.msg.local_id == Some(local_id))
+                    {
+                        entry.msg.id = msg_id;
+                    }
+                }
+            }
+            Message::Sent(local_id, Err(err)) => {
+                error!("{}", err);
+                if let App::Loaded(state) = self {
+                    let pending = state
+                        .chat
+                        .iter_mut()
+                        .find(|entry| entry.msg.local_id == Some(local_id));
+                    if let Some(entry) = pending {
+                        entry.msg.state = MessageState::OutFailed;
+                        entry.msg.error = Some(err.clone());
+                    }
+                    // The message is lost once its chat is left, so always tell about it.
+                    state.error = Some(err);
+                }
+            }
+            Message::Retried(_, Ok(())) => {}
+            Message::Retried(id, Err(err)) => {
+                error!("{}", err);
+                if let App::Loaded(State { chat, .. }) = self {
+                    if let Some(entry) = chat.iter_mut().find(|entry| entry.msg.id == id) {
+                        entry.msg.error = Some(err);
+                        entry.show_error = true;
+                    }
+                }
+            }
+            Message::Refreshed(Ok(Some(view))) => {
+                if let App::Loaded(state) = self {
+                    state.apply_view(view);
+                    return Command::batch(vec![state.load_posters(), state.round_avatars()]);
+                }
+            }
+            Message::PosterLoaded(id, Some(poster)) => {
+                if let App::Loaded(State { chat, .. }) = self {
+                    if let Some(entry) = chat.iter_mut().find(|entry| entry.msg.id == id) {
+                        entry.msg.poster = Some(poster);
+                    }
+                }
+            }
+            Message::PosterLoaded(_, None) | Message::AvatarRounded => {}
+            Message::Refreshed(Ok(None)) => {}
+            Message::Refreshed(Err(err)) => {
+                error!("{}", err);
+            }
+            Message::Pasted(pasted) => {
+                if let App::Loaded(state) = self {
+                    // The user might have switched to another chat in the meantime.
+                    let chat_id = state.selected_chat.as_ref().map(|chat| chat.id);
+                    let pasting = state.pasting.take();
+                    let same_chat = pasting.is_some() && pasting == chat_id;
+                    match pasted {
+                        Ok(Some(Pasted::Image(attachment))) if same_chat => {
+                            state.composer.set_attachment(attachment);
+                        }
+                        Ok(Some(Pasted::Image(attachment))) => {
+                            async_std::task::spawn(attachment.discard());
+                        }
+                        Ok(Some(Pasted::Text(text))) if same_chat => {
+                            state.composer.paste_text(&text);
+                        }
+                        Ok(Some(Pasted::Text(_))) | Ok(None) => {}
+                        Err(err) => {
+                            error!("{}", err);
+                            state.error = Some(err);
+                        }
+                    }
+                }
+            }
+            Message::NativeEvent(iced_native::Event::Keyboard(ev)) => {
+                if let App::Loaded(state) = self {
+                    let paste = Composer::is_paste(&ev);
+                    state.composer.on_keyboard_event(ev);
+
+                    // Pasting only stages something when the composer is visible.
+                    let can_send = state.setup.is_none()
+                        && state.viewer.is_none()
+                        && state.forward.is_none()
+                        && state.msg_info.is_none()
+                        && state
+                            .selected_chat
+                            .as_ref()
+                            .map_or(false, |chat| chat.can_send);
+                    if paste && can_send {
+                        if let Some(account) = state.accounts.active() {
+                            state.pasting = state.selected_chat.as_ref().map(|chat| chat.id);
+                            return Command::perform(
+                                media::paste(account.blobdir()),
+                                Message::Pasted,
+                            );
+                        }
+                    }
+                }
+            }
+            Message::NativeEvent(iced_native::Event::Window(
+                iced_native::window::Event::FileDropped(path),
+            )) => {
+                if let App::Loaded(state) = self {
+                    let chat_id = match &state.selected_chat {
+                        Some(chat) if chat.can_send => chat.id,
+                        _ => return Command::none(),
+                    };
+
+                    // Dropping several files sends an event for each of them.
+                    if state.loading_attachment.is_some() || state.composer.has_attachment() {
+                        state.error = Some(format!(
+                            "Only one file can be sent at a time, {} was not attached.",
+                            path.display()
+                        ));
+                        return Command::none();
+                    }
+
+                    state.loading_attachment = Some(chat_id);
+                    return Command::perform(Attachment::load(path), Message::AttachmentLoaded);
+                }
+            }
+            Message::NativeEvent(iced_native::Event::Mouse(
+                iced_native::input::mouse::Event::Input {
+                    state: iced_native::input::ButtonState::Pressed,
+                    button: iced_native::input::mouse::Button::Left,
+                },
+            )) => {
+                if let App::Loaded(State {
+                    mouse_pressed_at, ..
+                }) = self
+                {
+                    *mouse_pressed_at = Some(Instant::now());
+                }
+            }
+            Message::NativeEvent(iced_native::Event::Mouse(
+                iced_native::input::mouse::Event::Input {
+                    state: iced_native::input::ButtonState::Released,
+                    button: iced_native::input::mouse::Button::Left,
+                },
+            )) => {
+                // Native events are delivered after the widgets handled them, so the button
+                // reported this click already. Forgetting the press keeps it from counting
+                // for the next one.
+                if let App::Loaded(State {
+                    mouse_pressed_at, ..
+                }) = self
+                {
+                    *mouse_pressed_at = None;
+                }
+            }
+            Message::NativeEvent(_) => {}
+            Message::TimeFormatChanged(format) => {
+                if let App::Loaded(State { time_format, .. }) = self {
+                    *time_format = format;
+                    return Command::perform(format.save(), Message::TimeFormatSaved);
+                }
+            }
+            Message::TimeFormatSaved(Ok(())) => {}
+            Message::TimeFormatSaved(Err(err)) => {
+                error!("failed to save time format: {}", err);
+            }
+            Message::Tick => {}
             Message::Loaded(Err(err)) => {
-                // TODO: proper error handling
                 error!("{}", err);
+                *self = App::Failed(err);
             }
         }
         Command::none()
     }
 
     fn view(&mut self) -> Element<Self::Message> {
-        if let App::Loaded(State {
-            scroll_chat,
-            scroll_chat_list,
-            chat_list,
-            chat,
-            chat_name,
-            ..
-        }) = self
-        {
-            let chats: Element<_> = chat_list
-                .iter_mut()
-                .fold(Column::new().spacing(5), |column, entry| {
+        match self {
+            App::Loaded(State {
+                setup: Some(setup), ..
+            })
+            | App::Setup(setup) => Container::new(setup.view().map(Message::Setup))
+                .width(Length::Fill)
+                .height(Length::Fill)
+                .padding(40)
+                .center_x()
+                .center_y()
+                .into(),
+            App::Loaded(State {
+                account_list,
+                add_account_state,
+                backup,
+                scroll_chat,
+                scroll_chat_list,
+                chat_search,
+                contact_requests,
+                chat_list,
+                search_results,
+                chat,
+                chat_paging,
+                selected_chat,
+                composer,
+                viewer,
+                forward,
+                msg_info,
+                time_format,
+                error,
+                dismiss_error_state,
+                ..
+            }) => {
+                let time_format = *time_format;
+                let accounts: Element<_> = account_list
+                    .iter_mut()
+                    .fold(Column::new().spacing(5).padding(10), |column, entry| {
+                        let email = entry.email.clone();
+                        column.push(entry.view().map(move |message| {
+                            Message::AccountEntryMessage(email.clone(), message)
+                        }))
+                    })
+                    .push(
+                        button::Button::new(add_account_state, Text::new("Add account").size(16))
+                            .on_press(Message::AddAccount),
+                    )
+                    .push(backup.view().map(Message::Backup))
+                    .push(TimeFormat::ALL.iter().fold(
+                        Column::new().spacing(5),
+                        |column, format| {
+                            column.push(Radio::new(
+                                *format,
+                                &format.to_string(),
+                                Some(time_format),
+                                Message::TimeFormatChanged,
+                            ))
+                        },
+                    ))
+                    .into();
+                let mut chats = Column::new().spacing(5);
+                if !contact_requests.is_empty() {
+                    chats = chats.push(Text::new("Contact requests").color(Color::BLACK).size(18));
+                    chats = contact_requests.iter_mut().fold(chats, |column, entry| {
+                        let id = entry.request.contact_id;
+                        column.push(
+                            entry
+                                .view(time_format)
+                                .map(move |message| Message::ContactRequest(id, message)),
+                        )
+                    });
+                    chats = chats.push(Text::new("Chats").color(Color::BLACK).size(18));
+                }
+                let mut chats = chat_list.iter_mut().fold(chats, |column, entry| {
                     let id = entry.id.clone();
                     column.push(
                         entry
-                            .view()
+                            .view(time_format)
                             .map(move |message| Message::ChatListEntryMessage(id, message)),
                     )
-                })
-                .into();
-            let chat_el: Element<_> = chat
-                .iter_mut()
-                .fold(Column::new().spacing(2), |column, entry| {
+                });
+                if !search_results.is_empty() {
+                    chats = chats.push(Text::new("Messages").color(Color::BLACK).size(18));
+                    chats = search_results.iter_mut().fold(chats, |column, entry| {
+                        let id = entry.result.msg_id;
+                        column.push(
+                            entry
+                                .view(time_format)
+                                .map(move |message| Message::SearchResultEntryMessage(id, message)),
+                        )
+                    });
+                }
+                let chats: Element<_> = chats.into();
+                let can_send = selected_chat.as_ref().map_or(false, |chat| chat.can_send);
+                let mut chat_column = Column::new().spacing(2);
+                if let Some(older) = chat_paging.view_older() {
+                    chat_column = chat_column.push(older.map(Message::ChatPaging));
+                }
+                // Which messages start a new day, and which continue the block before them.
+                let layout = chat
+                    .iter()
+                    .enumerate()
+                    .map(|(i, entry)| {
+                        let day = format::day(entry.msg.timestamp);
+                        let prev = i.checked_sub(1).map(|i| &chat[i]);
+                        match prev {
+                            Some(prev) if format::day(prev.msg.timestamp) == day => {
+                                (None, entry.continues(prev))
+                            }
+                            _ => (Some(day), false),
+                        }
+                    })
+                    .collect::<Vec<_>>();
+
+                for (entry, (new_day, grouped)) in chat.iter_mut().zip(layout) {
+                    if let Some(day) = new_day {
+                        chat_column = chat_column.push(day_separator(day));
+                    }
+
                     let id = entry.msg.id.clone();
-                    column.push(
+                    chat_column = chat_column.push(
                         entry
-                            .view()
+                            .view(time_format, grouped, can_send)
                             .map(move |message| Message::ChatMessage(id, message)),
-                    )
-                })
-                .into();
+                    );
+                }
+                if let Some(newer) = chat_paging.view_newer() {
+                    chat_column = chat_column.push(newer.map(Message::ChatPaging));
+                }
+                let chat_el: Element<_> = chat_column.into();
 
-            let content = Column::new().push(
-                Row::new()
-                    .width(Length::Fill)
-                    .height(Length::Fill)
-                    .push(
-                        Column::new()
-                            .max_width(300)
+                let chat_pane = if let Some(viewer) = viewer {
+                    Column::new().push(viewer.view().map(Message::MediaViewer))
+                } else if let Some(forward) = forward {
+                    Column::new().push(forward.view().map(Message::ForwardPicker))
+                } else if let Some(msg_info) = msg_info {
+                    Column::new().push(msg_info.view().map(Message::MessageInfo))
+                } else if let Some(selected_chat) = selected_chat {
+                    Column::new()
+                        .push(
+                            Row::new()
+                                .spacing(10)
+                                .padding(10)
+                                .align_items(Align::Center)
+                                .push(avatar::view(
+                                    &selected_chat.name,
+                                    selected_chat.color,
+                                    selected_chat.profile_image.as_deref(),
+                                    32,
+                                ))
+                                .push(
+                                    Text::new(selected_chat.name.clone())
+                                        .color(Color::BLACK)
+                                        .size(20),
+                                ),
+                        )
+                        .push(
+                            Scrollable::new(scroll_chat)
+                                .padding(10)
+                                .height(Length::Fill)
+                                .push(chat_el),
+                        )
+                        .push(composer.view(selected_chat.can_send).map(Message::Composer))
+                } else {
+                    Column::new().push(Scrollable::new(scroll_chat).padding(10).push(chat_el))
+                };
+
+                let mut content = Column::new();
+                if let Some(error) = error {
+                    content = content.push(
+                        Row::new()
                             .spacing(10)
-                            .push(Scrollable::new(scroll_chat_list).padding(40).push(chats)),
-                    )
-                    .push(
-                        Column::new()
-                            .push(Text::new(chat_name.clone()).color(Color::BLACK).size(20))
-                            .push(Scrollable::new(scroll_chat).padding(10).push(chat_el)),
-                    ),
-            );
+                            .padding(10)
+                            .align_items(Align::Center)
+                            .push(
+                                Text::new(error.clone())
+                                    .size(16)
+                                    .color([0.8, 0.0, 0.0])
+                                    .width(Length::Fill),
+                            )
+                            .push(
+                                button::Button::new(dismiss_error_state, Text::new("×").size(16))
+                                    .on_press(Message::DismissError),
+                            ),
+                    );
+                }
+                let content = content.push(
+                    Row::new()
+                        .width(Length::Fill)
+                        .height(Length::Fill)
+                        .push(Column::new().max_width(250).push(accounts))
+                        .push(
+                            Column::new()
+                                .max_width(300)
+                                .spacing(10)
+                                .push(
+                                    Container::new(chat_search.view().map(Message::ChatSearch))
+                                        .padding(10),
+                                )
+                                .push(Scrollable::new(scroll_chat_list).padding(40).push(chats)),
+                        )
+                        .push(chat_pane),
+                );
 
-            Container::new(content)
-                .width(Length::Fill)
-                .center_x()
-                .into()
-        } else {
-            Container::new(
+                Container::new(content)
+                    .width(Length::Fill)
+                    .center_x()
+                    .into()
+            }
+            App::Loading => Container::new(
                 Text::new("Welcome to delta.cool")
                     .horizontal_alignment(HorizontalAlignment::Center)
                     .size(50),
@@ -188,43 +1349,410 @@ impl Application for App {
             .width(Length::Fill)
             .height(Length::Fill)
             .center_y()
-            .into()
+            .into(),
+            App::Failed(error) => Container::new(
+                Column::new()
+                    .spacing(10)
+                    .max_width(500)
+                    .push(
+                        Text::new("Failed to open your accounts")
+                            .horizontal_alignment(HorizontalAlignment::Center)
+                            .size(30)
+                            .width(Length::Fill),
+                    )
+                    .push(
+                        Text::new(error.clone())
+                            .horizontal_alignment(HorizontalAlignment::Center)
+                            .size(16)
+                            .color([0.8, 0.0, 0.0])
+                            .width(Length::Fill),
+                    ),
+            )
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .padding(40)
+            .center_x()
+            .center_y()
+            .into(),
         }
     }
 }
 
-async fn load_state() -> std::result::Result<State, String> {
-    let account = Account::new("d@testrun.org")
+impl App {
+    fn setup_mut(&mut self) -> Option<&mut Setup> {
+        match self {
+            App::Setup(setup) => Some(setup),
+            App::Loaded(state) => state.setup.as_mut(),
+            App::Loading | App::Failed(_) => None,
+        }
+    }
+
+    /// Whether the button that was just pressed has been held down for a while.
+    ///
+    /// Forgets the press, so it only counts for one button. Without a recorded press, e.g. if
+    /// its event did not arrive yet, it is a short one.
+    fn take_long_press(&mut self) -> bool {
+        match self {
+            App::Loaded(State {
+                mouse_pressed_at, ..
+            }) => mouse_pressed_at
+                .take()
+                .map_or(false, |pressed_at| pressed_at.elapsed() >= LONG_PRESS),
+            _ => false,
+        }
+    }
+
+    /// The attachment of a loaded message of the selected chat.
+    fn message_file(&self, id: deltachat::message::MsgId) -> Option<PathBuf> {
+        match self {
+            App::Loaded(State { chat, .. }) => chat
+                .iter()
+                .find(|entry| entry.msg.id == id)
+                .and_then(|entry| entry.msg.file.clone()),
+            _ => None,
+        }
+    }
+
+    /// Switches to the loaded state, the views are filled in once they are built.
+    fn load(&mut self, accounts: Accounts) -> Command<Message> {
+        let command = refresh(&accounts);
+        *self = App::Loaded(State::new(accounts, View::default()));
+
+        command
+    }
+}
+
+/// Opens all configured accounts, `None` if there are none yet.
+async fn load_state() -> std::result::Result<Option<Accounts>, String> {
+    let accounts = Accounts::open().await.map_err(|err| err.to_string())?;
+    if accounts.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(accounts))
+}
+
+/// Sends to the chat the message was written in, even if another chat was selected meanwhile.
+async fn send(
+    account: Account,
+    chat_id: deltachat::chat::ChatId,
+    outgoing: Outgoing,
+) -> std::result::Result<deltachat::message::MsgId, String> {
+    let res = match outgoing {
+        Outgoing::Text(text) => account.send_text_message(chat_id, text).await,
+        // Pasted files are already in the blobdir, the core sends them from there.
+        Outgoing::File(attachment, caption) => {
+            account
+                .send_file_message(
+                    chat_id,
+                    attachment.viewtype,
+                    attachment.path.to_string_lossy().to_string(),
+                    caption,
+                    Some(attachment.mime.clone()),
+                )
+                .await
+        }
+    };
+
+    res.map_err(|err| err.to_string())
+}
+
+async fn login(
+    mut account: Account,
+    email: String,
+    password: String,
+    imap: ServerSettings,
+    smtp: ServerSettings,
+) -> std::result::Result<(String, Account), String> {
+    account
+        .login(&email, &password, &imap, &smtp)
         .await
         .map_err(|err| err.to_string())?;
+    account
+        .load_chat_list()
+        .await
+        .map_err(|err| err.to_string())?;
+
+    Ok((email, account))
+}
+
+/// Applies an event to the account it belongs to, notifying about messages the user can not see.
+async fn handle_event(
+    accounts: Accounts,
+    email: String,
+    ev: deltachat::Event,
+) -> std::result::Result<Option<View>, String> {
+    let account = match accounts.get(&email) {
+        Some(account) => account,
+        None => return Ok(None),
+    };
 
-    let chat_list = account
-        .state
-        .read()
+    if let deltachat::Event::IncomingMsg { chat_id, msg_id } = ev {
+        let is_open = accounts.is_active(&email)
+            && account.state.read().await.selected_chat_id == Some(chat_id);
+        if !is_open {
+            if let Err(err) = account.notify_incoming(chat_id, msg_id).await {
+                warn!("{}", err);
+            }
+        }
+    }
+
+    let changed = account
+        .handle_event(&ev)
         .await
-        .chat_states
-        .iter()
-        .map(|(_, chat)| ChatListEntry::new(chat))
-        .collect();
+        .map_err(|err| err.to_string())?;
+    if changed {
+        Ok(Some(load_view(&accounts).await))
+    } else {
+        Ok(None)
+    }
+}
+
+async fn select_chat(
+    accounts: Accounts,
+    chat_id: deltachat::chat::ChatId,
+) -> std::result::Result<Option<View>, String> {
+    if let Some(account) = accounts.active() {
+        let mut account = account.clone();
+        account
+            .select_chat(chat_id)
+            .await
+            .map_err(|err| err.to_string())?;
+        account
+            .load_message_list()
+            .await
+            .map_err(|err| err.to_string())?;
+        info!("selected chat: {}", chat_id);
+    }
+
+    Ok(Some(load_view(&accounts).await))
+}
+
+async fn contact_request(
+    accounts: Accounts,
+    msg_id: deltachat::message::MsgId,
+    contact_id: u32,
+    msg: ContactRequestMessage,
+) -> std::result::Result<Option<View>, String> {
+    if let Some(account) = accounts.active() {
+        let res = match msg {
+            ContactRequestMessage::Accept => match account.create_chat_by_id(msg_id).await {
+                Ok(chat_id) => {
+                    let mut account = account.clone();
+                    match account.select_chat(chat_id).await {
+                        Ok(()) => account.load_message_list().await,
+                        Err(err) => Err(err),
+                    }
+                }
+                Err(err) => Err(err),
+            },
+            ContactRequestMessage::Block => account.block_contact(contact_id).await,
+            ContactRequestMessage::Ignore => account.ignore_contact_request(contact_id).await,
+        };
+        res.map_err(|err| err.to_string())?;
+    }
+
+    Ok(Some(load_view(&accounts).await))
+}
+
+async fn chat_action(
+    accounts: Accounts,
+    chat_id: deltachat::chat::ChatId,
+    action: ChatAction,
+) -> std::result::Result<Option<View>, String> {
+    use deltachat::chat::MuteDuration;
+
+    if let Some(account) = accounts.active() {
+        let res = match action {
+            ChatAction::SetVisibility(visibility) => {
+                account.set_chat_visibility(chat_id, visibility).await
+            }
+            ChatAction::Mute(Some(duration)) => {
+                let until = std::time::SystemTime::now() + duration;
+                account
+                    .set_chat_mute(chat_id, MuteDuration::Until(until))
+                    .await
+            }
+            ChatAction::Mute(None) => account.set_chat_mute(chat_id, MuteDuration::Forever).await,
+            ChatAction::Unmute => account.set_chat_mute(chat_id, MuteDuration::NotMuted).await,
+            ChatAction::ClearHistory => account.clear_chat_history(chat_id).await,
+            ChatAction::BlockContact => account.block_chat_contact(chat_id).await,
+            ChatAction::LeaveGroup => account.leave_group(chat_id).await,
+            ChatAction::Delete => account.delete_chat(chat_id).await,
+        };
+        res.map_err(|err| err.to_string())?;
+    }
+
+    Ok(Some(load_view(&accounts).await))
+}
+
+async fn message_action(
+    accounts: Accounts,
+    msg_id: deltachat::message::MsgId,
+    action: MessageAction,
+) -> std::result::Result<Option<View>, String> {
+    if let Some(account) = accounts.active() {
+        let res = match action {
+            MessageAction::Star(star) => account.star_message(msg_id, star).await,
+            MessageAction::Delete => account.delete_message(msg_id).await,
+            // Handled in `State::message_action`.
+            MessageAction::Reply
+            | MessageAction::Forward
+            | MessageAction::CopyText
+            | MessageAction::Info => Ok(()),
+        };
+        res.map_err(|err| err.to_string())?;
+    }
+
+    Ok(Some(load_view(&accounts).await))
+}
 
-    Ok(State {
-        account,
-        scroll_chat: Default::default(),
-        scroll_chat_list: Default::default(),
-        chat_list,
-        chat: Default::default(),
-        chat_name: Default::default(),
-    })
+/// Forwards a message and opens the chat it was forwarded to.
+async fn forward_message(
+    accounts: Accounts,
+    msg_id: deltachat::message::MsgId,
+    chat_id: deltachat::chat::ChatId,
+) -> std::result::Result<Option<View>, String> {
+    if let Some(account) = accounts.active() {
+        account
+            .forward_message(msg_id, chat_id)
+            .await
+            .map_err(|err| err.to_string())?;
+        let mut account = account.clone();
+        account
+            .select_chat(chat_id)
+            .await
+            .map_err(|err| err.to_string())?;
+        account
+            .load_message_list()
+            .await
+            .map_err(|err| err.to_string())?;
+    }
+
+    Ok(Some(load_view(&accounts).await))
+}
+
+async fn set_chat_filter(
+    accounts: Accounts,
+    filter: ChatListFilter,
+) -> std::result::Result<Option<View>, String> {
+    if let Some(account) = accounts.active() {
+        account
+            .set_chat_filter(filter)
+            .await
+            .map_err(|err| err.to_string())?;
+    }
+
+    Ok(Some(load_view(&accounts).await))
+}
+
+async fn jump_to_message(
+    accounts: Accounts,
+    msg_id: deltachat::message::MsgId,
+) -> std::result::Result<Option<View>, String> {
+    if let Some(account) = accounts.active() {
+        let mut account = account.clone();
+        account
+            .jump_to_message(msg_id)
+            .await
+            .map_err(|err| err.to_string())?;
+    }
+
+    Ok(Some(load_view(&accounts).await))
+}
+
+async fn load_page(
+    accounts: Accounts,
+    msg: ChatPagingMessage,
+) -> std::result::Result<Option<View>, String> {
+    if let Some(account) = accounts.active() {
+        let res = match msg {
+            ChatPagingMessage::LoadOlder => account.load_older_messages().await,
+            ChatPagingMessage::LoadNewer => account.load_newer_messages().await,
+        };
+        res.map_err(|err| err.to_string())?;
+    }
+
+    Ok(Some(load_view(&accounts).await))
+}
+
+fn refresh(accounts: &Accounts) -> Command<Message> {
+    let accounts = accounts.clone();
+    Command::perform(
+        async move { Ok(Some(load_view(&accounts).await)) },
+        Message::Refreshed,
+    )
+}
+
+/// Rebuilds the view models from the current account state.
+async fn load_view(accounts: &Accounts) -> View {
+    let mut account_list = Vec::new();
+    for (email, account) in accounts.iter() {
+        account_list.push(AccountEntry::new(
+            email.clone(),
+            account.fresh_msg_cnt().await,
+            accounts.is_active(email),
+        ));
+    }
+
+    let mut view = View {
+        account_list,
+        active: accounts.active_email().map(|email| email.to_string()),
+        ..Default::default()
+    };
+
+    if let Some(account) = accounts.active() {
+        let state = account.state.read().await;
+
+        view.chat_filter = state.chat_filter.clone();
+        view.contact_requests = state
+            .contact_requests
+            .iter()
+            .cloned()
+            .map(ContactRequestEntry::new)
+            .collect();
+        view.chat_list = state.chat_list().map(ChatListEntry::new).collect();
+        view.search_results = state
+            .msg_search_results
+            .iter()
+            .cloned()
+            .map(SearchResultEntry::new)
+            .collect();
+        view.chat = state
+            .chat_msgs
+            .iter()
+            .map(|(_, msg)| {
+                let mut entry = ChatMsg::new(msg.clone());
+                entry.highlighted = state.highlighted_msg == Some(msg.id);
+                entry
+            })
+            .collect();
+        view.chat_paging = ChatPaging::new(&state);
+        view.selected_chat = state.selected_chat.clone();
+    }
+
+    view
+}
+
+fn event_subscription(email: &str, account: &Account) -> Subscription<Message> {
+    Subscription::from_recipe(EventSubscription::new(email.to_string(), account))
+        .map(|(email, ev)| Message::Event(email, ev))
 }
 
 struct EventSubscription {
+    /// The account the events belong to
+    email: String,
+    /// Kept while the subscription runs, so the account is not deleted underneath it
+    account: Account,
     events: Option<deltachat::EventEmitter>,
 }
 
 impl EventSubscription {
-    fn new(events: deltachat::EventEmitter) -> Self {
+    fn new(email: String, account: &Account) -> Self {
         EventSubscription {
-            events: Some(events),
+            email,
+            account: account.clone(),
+            events: Some(account.get_event_emitter()),
         }
     }
 }
@@ -234,21 +1762,56 @@ impl<H, I> iced_native::subscription::Recipe<H, I> for EventSubscription
 where
     H: std::hash::Hasher,
 {
-    type Output = deltachat::Event;
+    type Output = (String, deltachat::Event);
 
     fn hash(&self, state: &mut H) {
         use std::hash::Hash;
 
         std::any::TypeId::of::<Self>().hash(state);
+        self.email.hash(state);
     }
 
     fn stream(
         mut self: Box<Self>,
         _input: futures::stream::BoxStream<'static, I>,
     ) -> futures::stream::BoxStream<'static, Self::Output> {
+        let email = self.email.clone();
+        let account = self.account.clone();
         Box::pin(futures::stream::unfold(
-            self.events.take().expect("missing events"),
-            |events| async move { events.recv().await.map(|ev| (ev, events)) },
+            (self.events.take().expect("missing events"), account),
+            move |(events, account)| {
+                let email = email.clone();
+                async move {
+                    events
+                        .recv()
+                        .await
+                        .map(|ev| ((email, ev), (events, account)))
+                }
+            },
         ))
     }
 }
+
+/// Fires every given interval, e.g. to update relative times.
+struct Every(Duration);
+
+impl<H, I> iced_native::subscription::Recipe<H, I> for Every
+where
+    H: std::hash::Hasher,
+{
+    type Output = ();
+
+    fn hash(&self, state: &mut H) {
+        use std::hash::Hash;
+
+        std::any::TypeId::of::<Self>().hash(state);
+        self.0.hash(state);
+    }
+
+    fn stream(
+        self: Box<Self>,
+        _input: futures::stream::BoxStream<'static, I>,
+    ) -> futures::stream::BoxStream<'static, Self::Output> {
+        Box::pin(async_std::stream::interval(self.0))
+    }
+}
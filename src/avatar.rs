@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use iced::{container, Background, Color, Container, Element, Image, Length, Text};
+use lazy_static::lazy_static;
+use log::*;
+
+use crate::media;
+
+/// Size of the rounded copies of profile images.
+const ROUND_SIZE: u32 = 128;
+
+lazy_static! {
+    /// Rounded copies of profile images, `None` while they are being made.
+    static ref ROUNDED: Mutex<HashMap<PathBuf, Option<PathBuf>>> = Default::default();
+}
+
+/// Avatar of a contact or chat: its image, or the first letter of its name on its color.
+///
+/// The image is only shown once `round` made a rounded copy of it.
+pub fn view<'a, Message: 'a>(
+    name: &str,
+    color: u32,
+    image: Option<&Path>,
+    size: u16,
+) -> Element<'a, Message> {
+    let rounded = image.and_then(|image| ROUNDED.lock().unwrap().get(image).cloned().flatten());
+    if let Some(image) = rounded {
+        return Image::new(image)
+            .width(Length::Units(size))
+            .height(Length::Units(size))
+            .into();
+    }
+
+    struct Style {
+        color: Color,
+        radius: u16,
+    }
+    impl container::StyleSheet for Style {
+        fn style(&self) -> container::Style {
+            container::Style {
+                background: Some(Background::Color(self.color)),
+                border_radius: self.radius,
+                text_color: Some(Color::WHITE),
+                ..container::Style::default()
+            }
+        }
+    }
+
+    let letter = name
+        .chars()
+        .find(|c| c.is_alphanumeric())
+        .map(|c| c.to_uppercase().to_string())
+        .unwrap_or_else(|| "#".into());
+
+    Container::new(Text::new(letter).size(size / 2))
+        .width(Length::Units(size))
+        .height(Length::Units(size))
+        .center_x()
+        .center_y()
+        .style(Style {
+            color: self::color(color),
+            radius: size / 2,
+        })
+        .into()
+}
+
+/// Converts a color as the core reports them for contacts and chats, `0xRRGGBB`.
+pub fn color(color: u32) -> Color {
+    Color::from_rgb8((color >> 16) as u8, (color >> 8) as u8, color as u8)
+}
+
+/// Whether `round` still has to be called for the image before `view` shows it.
+pub fn needs_round(file: &Path) -> bool {
+    !ROUNDED.lock().unwrap().contains_key(file)
+}
+
+/// Makes a copy of a profile image cut into a circle for `view`, cached in the user cache
+/// directory.
+///
+/// Falls back to the original image if it can not be processed.
+pub async fn round(file: PathBuf) {
+    {
+        let mut rounded = ROUNDED.lock().unwrap();
+        if rounded.contains_key(&file) {
+            return;
+        }
+        rounded.insert(file.clone(), None);
+    }
+
+    let rounded = round_file(&file).await;
+    ROUNDED.lock().unwrap().insert(file, Some(rounded));
+}
+
+async fn round_file(file: &Path) -> PathBuf {
+    // A changed image gets a new copy.
+    let modified = async_std::fs::metadata(file)
+        .await
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|modified| modified.as_secs())
+        .unwrap_or_default();
+
+    let rounded = match dirs::cache_dir() {
+        Some(dir) => dir.join("delta-cool").join("avatars").join(format!(
+            "{}-{}.png",
+            media::cache_key(file),
+            modified
+        )),
+        None => return file.to_path_buf(),
+    };
+    if rounded.exists() {
+        return rounded;
+    }
+
+    let res = {
+        let (file, rounded) = (file.to_path_buf(), rounded.clone());
+        async_std::task::spawn_blocking(move || write_round(&file, &rounded)).await
+    };
+    match res {
+        Ok(()) => rounded,
+        Err(err) => {
+            warn!("failed to round avatar {}: {}", file.display(), err);
+            file.to_path_buf()
+        }
+    }
+}
+
+fn write_round(file: &Path, target: &Path) -> anyhow::Result<()> {
+    use ::image::GenericImageView;
+
+    let resized = ::image::open(file)?.resize_to_fill(
+        ROUND_SIZE,
+        ROUND_SIZE,
+        ::image::imageops::FilterType::Triangle,
+    );
+
+    let radius = ROUND_SIZE as f32 / 2.0;
+    let img = ::image::RgbaImage::from_fn(ROUND_SIZE, ROUND_SIZE, |x, y| {
+        let mut pixel = resized.get_pixel(x, y);
+        let dx = x as f32 + 0.5 - radius;
+        let dy = y as f32 + 0.5 - radius;
+        // Fade out over one pixel to smooth the edge.
+        let coverage = (radius - (dx * dx + dy * dy).sqrt()).max(0.0).min(1.0);
+        pixel[3] = (pixel[3] as f32 * coverage) as u8;
+        pixel
+    });
+
+    if let Some(dir) = target.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    img.save(target)?;
+
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use std::path::PathBuf;
+
+use iced::{button, Column, Element, Length, ProgressBar, Text};
+
+/// Export of a backup of the active account.
+#[derive(Debug, Default)]
+pub struct Backup {
+    /// The account that is being exported.
+    pub email: Option<String>,
+    /// Export progress, in permille.
+    pub progress: Option<usize>,
+    pub written: Option<PathBuf>,
+    pub error: Option<String>,
+    export_state: button::State,
+}
+
+#[derive(Debug, Clone)]
+pub enum BackupMessage {
+    Export,
+}
+
+impl Backup {
+    /// Whether an export of the given account is running.
+    pub fn is_running(&self, email: &str) -> bool {
+        self.progress.is_some() && self.email.as_deref() == Some(email)
+    }
+
+    pub fn finish(&mut self, result: Result<(), String>) {
+        self.progress = None;
+        self.error = result.err();
+    }
+
+    pub fn start(&mut self, email: String) {
+        self.email = Some(email);
+        self.progress = Some(0);
+        self.written = None;
+        self.error = None;
+    }
+
+    pub fn view(&mut self) -> Element<BackupMessage> {
+        let mut export =
+            button::Button::new(&mut self.export_state, Text::new("Export backup").size(16))
+                .width(Length::Fill);
+        if self.progress.is_none() {
+            export = export.on_press(BackupMessage::Export);
+        }
+
+        let mut column = Column::new().spacing(5).push(export);
+
+        if let Some(progress) = self.progress {
+            column = column.push(ProgressBar::new(0.0..=1000.0, progress as f32));
+        }
+        if let Some(written) = &self.written {
+            column = column.push(
+                Text::new(format!("Backup written to {}", written.display()))
+                    .size(14)
+                    .color([0.5, 0.5, 0.5]),
+            );
+        }
+        if let Some(error) = &self.error {
+            column = column.push(Text::new(error.clone()).size(14).color([0.8, 0.0, 0.0]));
+        }
+
+        column.into()
+    }
+}
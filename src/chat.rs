@@ -1,93 +1,925 @@
-use super::account;
+use std::path::Path;
+
+use super::{account, avatar, format, media};
+use deltachat::chat::ChatId;
+use deltachat::constants::{Viewtype, DC_CONTACT_ID_SELF};
+use deltachat::message::MessageState;
 use iced::{
-    button, image, Background, Color, Column, Element, HorizontalAlignment, Length, Row, Space,
-    Text,
+    button, container, image, scrollable, Align, Background, Color, Column, Container, Element,
+    HorizontalAlignment, Length, Row, Scrollable, Space, Text,
 };
+use time::Duration;
+
+/// Messages of the same sender at most this far apart are shown as one block.
+const GROUP_WINDOW: Duration = Duration::minutes(5);
 
 #[derive(Debug, Clone)]
 pub struct ChatMsg {
     pub msg: account::ChatMessage,
-    pub button_state: button::State,
+    pub media_state: button::State,
+    pub open_state: button::State,
+    pub save_state: button::State,
+    /// Whether the error of a failed message is shown
+    pub show_error: bool,
+    pub error_state: button::State,
+    pub retry_state: button::State,
+    /// Whether the timestamp of a grouped message stays visible without hovering
+    pub show_timestamp: bool,
+    pub timestamp_state: button::State,
+    /// Whether the user jumped to this message
+    pub highlighted: bool,
+    /// Whether the actions for this message are shown
+    pub show_actions: bool,
+    pub confirm_delete: bool,
+    actions_state: button::State,
+    star_state: button::State,
+    reply_state: button::State,
+    forward_state: button::State,
+    copy_state: button::State,
+    info_state: button::State,
+    delete_state: button::State,
 }
 
 #[derive(Debug, Clone)]
-pub enum ChatMsgMessage {}
+pub enum ChatMsgMessage {
+    OpenMedia,
+    OpenFile,
+    SaveFile,
+    ToggleError,
+    Retry,
+    ToggleTimestamp,
+    ToggleActions,
+    Delete,
+    Action(MessageAction),
+}
+
+/// Things to do with a message, offered in its action menu.
+#[derive(Debug, Clone, Copy)]
+pub enum MessageAction {
+    Star(bool),
+    /// Quotes the message in the composer
+    Reply,
+    Forward,
+    CopyText,
+    Info,
+    /// Deletes the message on this account, the core can not delete it for the recipients.
+    Delete,
+}
 
 impl ChatMsg {
     pub fn new(msg: account::ChatMessage) -> Self {
         Self {
             msg,
-            button_state: Default::default(),
+            media_state: Default::default(),
+            open_state: Default::default(),
+            save_state: Default::default(),
+            show_error: false,
+            error_state: Default::default(),
+            retry_state: Default::default(),
+            show_timestamp: false,
+            timestamp_state: Default::default(),
+            highlighted: false,
+            show_actions: false,
+            confirm_delete: false,
+            actions_state: Default::default(),
+            star_state: Default::default(),
+            reply_state: Default::default(),
+            forward_state: Default::default(),
+            copy_state: Default::default(),
+            info_state: Default::default(),
+            delete_state: Default::default(),
+        }
+    }
+
+    /// Applies the messages that only change the entry, returns the action to run on the message.
+    ///
+    /// Everything else has to be handled by the caller.
+    pub fn update(&mut self, message: ChatMsgMessage) -> Option<MessageAction> {
+        match message {
+            ChatMsgMessage::ToggleError => self.show_error = !self.show_error,
+            ChatMsgMessage::ToggleTimestamp => self.show_timestamp = !self.show_timestamp,
+            ChatMsgMessage::ToggleActions => {
+                self.show_actions = !self.show_actions;
+                self.confirm_delete = false;
+            }
+            ChatMsgMessage::Delete => self.confirm_delete = true,
+            ChatMsgMessage::Action(action) => {
+                self.show_actions = false;
+                return Some(action);
+            }
+            ChatMsgMessage::OpenMedia
+            | ChatMsgMessage::OpenFile
+            | ChatMsgMessage::SaveFile
+            | ChatMsgMessage::Retry => {}
         }
+
+        None
     }
 
-    pub fn view(&mut self) -> Element<ChatMsgMessage> {
-        let row = Row::new().spacing(20);
+    /// Whether `self` is shown in the same block as `prev`, the message right before it.
+    pub fn continues(&self, prev: &ChatMsg) -> bool {
+        let (prev, msg) = (&prev.msg, &self.msg);
+        let elapsed = msg.timestamp - prev.timestamp;
 
-        let row = if self.msg.is_info {
-            // Info messages
-            row.push(
+        !prev.is_info
+            && !msg.is_info
+            && prev.from_id == msg.from_id
+            && elapsed >= Duration::zero()
+            && elapsed <= GROUP_WINDOW
+    }
+
+    /// Renders the message, `grouped` ones leave out the avatar and sender of their block.
+    /// `can_send` tells whether the chat can be written to, so the message can be replied to.
+    pub fn view(
+        &mut self,
+        time_format: format::TimeFormat,
+        grouped: bool,
+        can_send: bool,
+    ) -> Element<ChatMsgMessage> {
+        if self.msg.is_info {
+            return Container::new(
                 Text::new(self.msg.text.as_ref().cloned().unwrap_or_default())
                     .horizontal_alignment(HorizontalAlignment::Center)
                     .size(16)
-                    .color([0.5, 0.5, 0.5])
-                    .width(Length::Fill),
+                    .color([0.5, 0.5, 0.5]),
             )
+            .padding(5)
+            .width(Length::Fill)
+            .center_x()
+            .into();
+        }
+
+        let is_self = self.msg.from_id == DC_CONTACT_ID_SELF;
+        let mut content = Column::new().spacing(5);
+
+        if !grouped {
+            let mut header = Row::new().spacing(10);
+            if !is_self {
+                header = header.push(
+                    Text::new(self.msg.from_first_name.clone())
+                        .size(18)
+                        .color(avatar::color(self.msg.from_color)),
+                );
+            }
+            header = header.push(
+                Text::new(format::time(self.msg.timestamp, time_format))
+                    .size(16)
+                    .color([0.5, 0.5, 0.5]),
+            );
+            if self.msg.starred {
+                header = header.push(Text::new("★").size(16).color(STAR_COLOR));
+            }
+            if is_self {
+                header = header.push(view_state(self.msg.state, &mut self.error_state));
+            }
+            content = content.push(header);
+        }
+
+        if let Some(media) = view_media(&self.msg, &mut self.media_state) {
+            content = content.push(media);
+        }
+
+        if let Some(card) = view_file(&self.msg, &mut self.open_state, &mut self.save_state) {
+            content = content.push(card);
+        }
+
+        let text = self
+            .msg
+            .text
+            .as_ref()
+            .filter(|text| !text.is_empty())
+            .map(|text| Text::new(text.clone()).size(18).color(Color::BLACK));
+
+        if grouped {
+            // The timestamp is only shown while hovering it, as it has no color of its own
+            // it gets the text color of the button style.
+            let mut line = Row::new()
+                .spacing(10)
+                .push(text.unwrap_or_else(|| Text::new("")))
+                .push(
+                    button::Button::new(
+                        &mut self.timestamp_state,
+                        Text::new(format::time(self.msg.timestamp, time_format)).size(16),
+                    )
+                    .padding(0)
+                    .style(HoverTimestampStyle {
+                        pinned: self.show_timestamp,
+                    })
+                    .on_press(ChatMsgMessage::ToggleTimestamp),
+                );
+            if self.msg.starred {
+                line = line.push(Text::new("★").size(16).color(STAR_COLOR));
+            }
+            if is_self {
+                line = line.push(view_state(self.msg.state, &mut self.error_state));
+            }
+            content = content.push(line);
+        } else if let Some(text) = text {
+            content = content.push(text);
+        }
+
+        if self.show_error && self.msg.state == MessageState::OutFailed {
+            content = content.push(view_error(&self.msg, &mut self.retry_state));
+        }
+
+        // Stickers are shown on their own, without a bubble.
+        let background = if self.msg.viewtype == Viewtype::Sticker {
+            None
+        } else if is_self {
+            Some(SELF_BUBBLE_COLOR)
+        } else {
+            Some(tint(avatar::color(self.msg.from_color)))
+        };
+        let bubble = Container::new(content).padding(10).style(BubbleStyle {
+            background,
+            highlighted: self.highlighted,
+        });
+
+        // Bubbles take up to three quarters of the chat, so their width follows the window.
+        let (align, spacer) = (
+            if is_self { Align::End } else { Align::Start },
+            Space::new(Length::FillPortion(1), Length::Shrink),
+        );
+        // Messages that are still being sent can not be acted on yet.
+        let bubble = if self.msg.local_id.is_some() {
+            Row::new().push(bubble)
         } else {
-            // Regular Messages
-            let row = if let Some(img) = &self.msg.from_profile_image {
-                let img = image::Image::new(img)
-                    .width(Length::Units(40))
-                    .height(Length::Units(40));
-                row.push(img)
+            let toggle = button::Button::new(&mut self.actions_state, Text::new("⋯").size(16))
+                .style(MediaStyle {})
+                .on_press(ChatMsgMessage::ToggleActions);
+            if is_self {
+                Row::new().spacing(5).push(toggle).push(bubble)
             } else {
-                row.push(Space::new(Length::Units(40), Length::Units(40)))
+                Row::new().spacing(5).push(bubble).push(toggle)
+            }
+        };
+        let mut bubble = Column::new()
+            .spacing(5)
+            .width(Length::FillPortion(3))
+            .align_items(align)
+            .push(bubble);
+        if self.show_actions {
+            bubble = bubble.push(view_actions(
+                &self.msg,
+                self.confirm_delete,
+                can_send,
+                ActionStates {
+                    star: &mut self.star_state,
+                    reply: &mut self.reply_state,
+                    forward: &mut self.forward_state,
+                    copy: &mut self.copy_state,
+                    info: &mut self.info_state,
+                    delete: &mut self.delete_state,
+                },
+            ));
+        }
+
+        let row = if is_self {
+            Row::new().push(spacer).push(bubble)
+        } else {
+            let avatar = if grouped {
+                Space::new(Length::Units(40), Length::Shrink).into()
+            } else {
+                avatar::view(
+                    &self.msg.from_first_name,
+                    self.msg.from_color,
+                    self.msg.from_profile_image.as_deref(),
+                    40,
+                )
             };
+            Row::new().push(avatar).push(bubble).push(spacer)
+        };
 
-            row.push(
-                Column::new()
-                    .spacing(5)
-                    .push(
-                        Row::new()
-                            .spacing(10)
-                            .push(
-                                Text::new(self.msg.from_first_name.clone())
-                                    .horizontal_alignment(HorizontalAlignment::Left)
-                                    .size(18)
-                                    .color(Color::BLACK)
-                                    .width(Length::Fill),
-                            )
-                            .push(
-                                Text::new(self.msg.timestamp.lazy_format("%r").to_string())
-                                    .horizontal_alignment(HorizontalAlignment::Left)
-                                    .size(16)
-                                    .color([0.5, 0.5, 0.5])
-                                    .width(Length::Fill),
-                            ),
+        Container::new(row.spacing(10))
+            .padding(if grouped { 1 } else { 5 })
+            .width(Length::Fill)
+            .into()
+    }
+}
+
+struct ActionStates<'a> {
+    star: &'a mut button::State,
+    reply: &'a mut button::State,
+    forward: &'a mut button::State,
+    copy: &'a mut button::State,
+    info: &'a mut button::State,
+    delete: &'a mut button::State,
+}
+
+fn view_actions<'a>(
+    msg: &account::ChatMessage,
+    confirm_delete: bool,
+    can_reply: bool,
+    states: ActionStates<'a>,
+) -> Element<'a, ChatMsgMessage> {
+    let action = |state, label: &str, msg_action| {
+        button::Button::new(state, Text::new(label).size(14))
+            .on_press(ChatMsgMessage::Action(msg_action))
+    };
+
+    let mut row = Row::new().spacing(5).push(if msg.starred {
+        action(states.star, "Unstar", MessageAction::Star(false))
+    } else {
+        action(states.star, "Star", MessageAction::Star(true))
+    });
+    if can_reply {
+        row = row.push(action(states.reply, "Reply", MessageAction::Reply));
+    }
+    row = row.push(action(states.forward, "Forward", MessageAction::Forward));
+    if msg.text.as_ref().map_or(false, |text| !text.is_empty()) {
+        row = row.push(action(states.copy, "Copy text", MessageAction::CopyText));
+    }
+    row = row
+        .push(action(states.info, "Info", MessageAction::Info))
+        .push(if confirm_delete {
+            button::Button::new(
+                states.delete,
+                Text::new("Delete for me?").size(14).color([0.8, 0.0, 0.0]),
+            )
+            .on_press(ChatMsgMessage::Action(MessageAction::Delete))
+        } else {
+            button::Button::new(states.delete, Text::new("Delete for me").size(14))
+                .on_press(ChatMsgMessage::Delete)
+        });
+
+    row.into()
+}
+
+const STAR_COLOR: Color = Color {
+    r: 0.9,
+    g: 0.7,
+    b: 0.0,
+    a: 1.0,
+};
+
+/// Background of the messages sent from this account.
+const SELF_BUBBLE_COLOR: Color = Color {
+    r: 0.86,
+    g: 0.95,
+    b: 0.86,
+    a: 1.0,
+};
+
+/// Light version of a color, to put black text on it.
+fn tint(color: Color) -> Color {
+    let mix = |c: f32| c * 0.15 + 0.85;
+    Color::from_rgb(mix(color.r), mix(color.g), mix(color.b))
+}
+
+struct BubbleStyle {
+    background: Option<Color>,
+    highlighted: bool,
+}
+
+impl container::StyleSheet for BubbleStyle {
+    fn style(&self) -> container::Style {
+        container::Style {
+            background: self.background.map(Background::Color),
+            text_color: Some(Color::BLACK),
+            border_radius: 10,
+            border_width: if self.highlighted { 2 } else { 0 },
+            border_color: Color::from_rgb(0.2, 0.5, 0.9),
+            ..container::Style::default()
+        }
+    }
+}
+
+struct HoverTimestampStyle {
+    pinned: bool,
+}
+
+impl button::StyleSheet for HoverTimestampStyle {
+    fn active(&self) -> button::Style {
+        button::Style {
+            background: None,
+            text_color: if self.pinned {
+                Color::from_rgb(0.5, 0.5, 0.5)
+            } else {
+                Color::TRANSPARENT
+            },
+            ..button::Style::default()
+        }
+    }
+
+    fn hovered(&self) -> button::Style {
+        button::Style {
+            text_color: Color::from_rgb(0.5, 0.5, 0.5),
+            ..self.active()
+        }
+    }
+}
+
+/// Row between messages of different days.
+pub fn day_separator<'a, Message: 'a>(day: time::Date) -> Element<'a, Message> {
+    Text::new(format::day_label(day))
+        .horizontal_alignment(HorizontalAlignment::Center)
+        .size(14)
+        .color([0.5, 0.5, 0.5])
+        .width(Length::Fill)
+        .into()
+}
+
+/// Delivery state of outgoing messages, failed ones can be pressed to show the error.
+fn view_state(state: MessageState, error_state: &mut button::State) -> Element<ChatMsgMessage> {
+    let (glyph, color) = match state {
+        MessageState::OutFailed => {
+            return button::Button::new(
+                error_state,
+                Text::new("⚠").size(16).color([0.8, 0.0, 0.0]),
+            )
+            .padding(0)
+            .style(MediaStyle {})
+            .on_press(ChatMsgMessage::ToggleError)
+            .into();
+        }
+        MessageState::OutDelivered => ("✓", Color::from_rgb(0.5, 0.5, 0.5)),
+        MessageState::OutMdnRcvd => ("✓✓", Color::from_rgb(0.2, 0.5, 0.9)),
+        MessageState::OutPreparing | MessageState::OutPending | MessageState::OutDraft => {
+            ("○", Color::from_rgb(0.5, 0.5, 0.5))
+        }
+        _ => ("", Color::BLACK),
+    };
+
+    Text::new(glyph).size(16).color(color).into()
+}
+
+/// Why a message failed to send, with the option to send it again.
+fn view_error<'a>(
+    msg: &account::ChatMessage,
+    retry_state: &'a mut button::State,
+) -> Element<'a, ChatMsgMessage> {
+    let error = msg
+        .error
+        .clone()
+        .unwrap_or_else(|| "Sending failed for an unknown reason.".into());
+
+    let mut row = Row::new().spacing(10).align_items(Align::Center).push(
+        Text::new(error)
+            .size(14)
+            .color([0.8, 0.0, 0.0])
+            .width(Length::Fill),
+    );
+    // Messages that never reached the core can not be sent again.
+    if !msg.id.is_unset() {
+        row = row.push(
+            button::Button::new(retry_state, Text::new("Retry").size(14))
+                .on_press(ChatMsgMessage::Retry),
+        );
+    }
+
+    Container::new(row)
+        .padding(10)
+        .max_width(400)
+        .style(ErrorStyle {})
+        .into()
+}
+
+struct ErrorStyle {}
+
+impl container::StyleSheet for ErrorStyle {
+    fn style(&self) -> container::Style {
+        container::Style {
+            background: Some(Background::Color(Color::from_rgb(1.0, 0.93, 0.93))),
+            border_radius: 5,
+            border_width: 1,
+            border_color: Color::from_rgb(0.8, 0.0, 0.0),
+            ..container::Style::default()
+        }
+    }
+}
+
+/// Inline preview of images, GIFs, stickers and videos, opening the viewer when pressed.
+fn view_media<'a>(
+    msg: &account::ChatMessage,
+    state: &'a mut button::State,
+) -> Option<Element<'a, ChatMsgMessage>> {
+    let max_size = match msg.viewtype {
+        Viewtype::Image | Viewtype::Gif | Viewtype::Video => media::MAX_MEDIA_SIZE,
+        Viewtype::Sticker => media::MAX_STICKER_SIZE,
+        _ => return None,
+    };
+    let size = media::fit(msg.file_width, msg.file_height, max_size);
+
+    let preview: Element<_> = match (msg.viewtype, &msg.file, &msg.poster) {
+        (Viewtype::Video, _, Some(poster)) => view_image(poster, size, max_size),
+        (Viewtype::Video, _, None) => {
+            let (width, height) = size.unwrap_or(max_size);
+            Container::new(Text::new("▶").size(40).color(Color::WHITE))
+                .width(Length::Units(width))
+                .height(Length::Units(height))
+                .center_x()
+                .center_y()
+                .style(VideoPlaceholderStyle {})
+                .into()
+        }
+        (_, Some(file), _) => view_image(file, size, max_size),
+        (_, None, _) => return None,
+    };
+
+    let preview: Element<_> = match msg.viewtype {
+        Viewtype::Video => Column::new()
+            .spacing(2)
+            .push(preview)
+            .push(
+                Text::new(format!("▶ {}", media::format_duration(msg.duration)))
+                    .size(14)
+                    .color([0.5, 0.5, 0.5]),
+            )
+            .into(),
+        Viewtype::Gif => Column::new()
+            .spacing(2)
+            .push(preview)
+            .push(Text::new("GIF").size(14).color([0.5, 0.5, 0.5]))
+            .into(),
+        _ => preview,
+    };
+
+    Some(
+        button::Button::new(state, preview)
+            .padding(0)
+            .style(MediaStyle {})
+            .on_press(ChatMsgMessage::OpenMedia)
+            .into(),
+    )
+}
+
+/// Shows an image at `size`, or scaled down into `max_size` if its size is unknown.
+fn view_image<'a>(
+    path: &Path,
+    size: Option<(u16, u16)>,
+    max_size: (u16, u16),
+) -> Element<'a, ChatMsgMessage> {
+    match size {
+        Some((width, height)) => image::Image::new(path)
+            .width(Length::Units(width))
+            .height(Length::Units(height))
+            .into(),
+        // The image keeps its aspect ratio when it is limited by the container.
+        None => Container::new(image::Image::new(path))
+            .max_width(max_size.0 as u32)
+            .max_height(max_size.1 as u32)
+            .into(),
+    }
+}
+
+/// Card for files, audio and voice messages, with actions to open and save them.
+fn view_file<'a>(
+    msg: &account::ChatMessage,
+    open_state: &'a mut button::State,
+    save_state: &'a mut button::State,
+) -> Option<Element<'a, ChatMsgMessage>> {
+    let icon = match msg.viewtype {
+        Viewtype::File => "📄",
+        Viewtype::Audio => "🎵",
+        Viewtype::Voice => "🎤",
+        _ => return None,
+    };
+    let file = msg.file.as_ref()?;
+
+    let name = file
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut details = vec![media::format_size(msg.file_bytes), media::mime_type(file)];
+    if msg.duration > 0 {
+        details.push(media::format_duration(msg.duration));
+    }
+
+    let card = Row::new()
+        .spacing(10)
+        .align_items(Align::Center)
+        .push(Text::new(icon).size(30))
+        .push(
+            Column::new()
+                .spacing(2)
+                .width(Length::Fill)
+                .push(Text::new(name).size(16).color(Color::BLACK))
+                .push(
+                    Text::new(details.join(" · "))
+                        .size(14)
+                        .color([0.5, 0.5, 0.5]),
+                ),
+        )
+        .push(
+            button::Button::new(open_state, Text::new("Open").size(14))
+                .on_press(ChatMsgMessage::OpenFile),
+        )
+        .push(
+            button::Button::new(save_state, Text::new("Save as…").size(14))
+                .on_press(ChatMsgMessage::SaveFile),
+        );
+
+    Some(
+        Container::new(card)
+            .padding(10)
+            .max_width(400)
+            .style(FileCardStyle {})
+            .into(),
+    )
+}
+
+struct FileCardStyle {}
+
+impl container::StyleSheet for FileCardStyle {
+    fn style(&self) -> container::Style {
+        container::Style {
+            background: Some(Background::Color(Color::from_rgb(0.95, 0.95, 0.95))),
+            border_radius: 5,
+            border_width: 1,
+            border_color: Color::from_rgb(0.85, 0.85, 0.85),
+            ..container::Style::default()
+        }
+    }
+}
+
+struct MediaStyle {}
+
+impl button::StyleSheet for MediaStyle {
+    fn active(&self) -> button::Style {
+        button::Style {
+            background: None,
+            ..button::Style::default()
+        }
+    }
+}
+
+struct VideoPlaceholderStyle {}
+
+impl container::StyleSheet for VideoPlaceholderStyle {
+    fn style(&self) -> container::Style {
+        container::Style {
+            background: Some(Background::Color(Color::BLACK)),
+            ..container::Style::default()
+        }
+    }
+}
+
+/// Full size view of the media of a message, shown instead of the chat.
+#[derive(Debug, Clone)]
+pub struct MediaViewer {
+    pub msg: account::ChatMessage,
+    close_state: button::State,
+}
+
+#[derive(Debug, Clone)]
+pub enum MediaViewerMessage {
+    Close,
+}
+
+impl MediaViewer {
+    pub fn new(msg: account::ChatMessage) -> Self {
+        Self {
+            msg,
+            close_state: Default::default(),
+        }
+    }
+
+    pub fn view(&mut self) -> Element<MediaViewerMessage> {
+        let path = match self.msg.viewtype {
+            Viewtype::Video => self.msg.poster.as_ref(),
+            _ => self.msg.file.as_ref(),
+        };
+
+        let mut column = Column::new().spacing(10).padding(10).push(
+            button::Button::new(&mut self.close_state, Text::new("Close").size(18))
+                .on_press(MediaViewerMessage::Close),
+        );
+
+        if let Some(path) = path {
+            column = column.push(
+                image::Image::new(path)
+                    .width(Length::Fill)
+                    .height(Length::Fill),
+            );
+        }
+
+        if self.msg.viewtype == Viewtype::Video {
+            column = column.push(
+                Text::new(media::format_duration(self.msg.duration))
+                    .size(16)
+                    .color([0.5, 0.5, 0.5]),
+            );
+        }
+
+        column.into()
+    }
+}
+
+/// Buttons to page through the history of the selected chat.
+#[derive(Debug, Clone, Default)]
+pub struct ChatPaging {
+    pub has_older: bool,
+    pub has_newer: bool,
+    older_state: button::State,
+    newer_state: button::State,
+}
+
+#[derive(Debug, Clone)]
+pub enum ChatPagingMessage {
+    LoadOlder,
+    LoadNewer,
+}
+
+impl ChatPaging {
+    pub fn new(state: &account::AccountState) -> Self {
+        Self {
+            has_older: state.has_older_msgs(),
+            has_newer: state.has_newer_msgs(),
+            ..Default::default()
+        }
+    }
+
+    pub fn view_older(&mut self) -> Option<Element<ChatPagingMessage>> {
+        if !self.has_older {
+            return None;
+        }
+
+        Some(page_button(
+            &mut self.older_state,
+            "Load older messages",
+            ChatPagingMessage::LoadOlder,
+        ))
+    }
+
+    pub fn view_newer(&mut self) -> Option<Element<ChatPagingMessage>> {
+        if !self.has_newer {
+            return None;
+        }
+
+        Some(page_button(
+            &mut self.newer_state,
+            "Load newer messages",
+            ChatPagingMessage::LoadNewer,
+        ))
+    }
+}
+
+fn page_button<'a>(
+    state: &'a mut button::State,
+    label: &str,
+    message: ChatPagingMessage,
+) -> Element<'a, ChatPagingMessage> {
+    button::Button::new(
+        state,
+        Text::new(label)
+            .horizontal_alignment(HorizontalAlignment::Center)
+            .size(16)
+            .width(Length::Fill),
+    )
+    .width(Length::Fill)
+    .on_press(message)
+    .into()
+}
+
+/// Picks the chat a message is forwarded to, shown instead of the chat.
+#[derive(Debug, Clone)]
+pub struct ForwardPicker {
+    pub msg_id: deltachat::message::MsgId,
+    chats: Vec<(ChatId, String, button::State)>,
+    scroll_state: scrollable::State,
+    cancel_state: button::State,
+}
+
+#[derive(Debug, Clone)]
+pub enum ForwardPickerMessage {
+    Select(ChatId),
+    Cancel,
+}
+
+impl ForwardPicker {
+    /// Offers the given chats, see `Account::forward_targets`.
+    pub fn new(
+        msg_id: deltachat::message::MsgId,
+        chats: impl IntoIterator<Item = (ChatId, String)>,
+    ) -> Self {
+        Self {
+            msg_id,
+            chats: chats
+                .into_iter()
+                .map(|(id, name)| (id, name, Default::default()))
+                .collect(),
+            scroll_state: Default::default(),
+            cancel_state: Default::default(),
+        }
+    }
+
+    pub fn view(&mut self) -> Element<ForwardPickerMessage> {
+        let chats =
+            self.chats
+                .iter_mut()
+                .fold(Column::new().spacing(5), |column, (id, name, state)| {
+                    column.push(
+                        button::Button::new(state, Text::new(name.clone()).size(18))
+                            .width(Length::Fill)
+                            .on_press(ForwardPickerMessage::Select(*id)),
                     )
+                });
+
+        Column::new()
+            .spacing(10)
+            .padding(10)
+            .push(
+                Row::new()
+                    .spacing(10)
+                    .align_items(Align::Center)
                     .push(
-                        Text::new(self.msg.text.as_ref().cloned().unwrap_or_default())
-                            .horizontal_alignment(HorizontalAlignment::Left)
-                            .size(18)
+                        Text::new("Forward to…")
                             .color(Color::BLACK)
+                            .size(20)
                             .width(Length::Fill),
+                    )
+                    .push(
+                        button::Button::new(&mut self.cancel_state, Text::new("Cancel").size(16))
+                            .on_press(ForwardPickerMessage::Cancel),
                     ),
             )
-        };
+            .push(
+                Scrollable::new(&mut self.scroll_state)
+                    .height(Length::Fill)
+                    .push(chats),
+            )
+            .into()
+    }
+}
 
-        struct Style {}
-        impl button::StyleSheet for Style {
-            fn active(&self) -> button::Style {
-                button::Style {
-                    background: Some(Background::Color(Color::WHITE)),
-                    text_color: Color::BLACK,
-                    ..button::Style::default()
-                }
-            }
+/// The info text of a message from the core, shown instead of the chat.
+#[derive(Debug, Clone)]
+pub struct MessageInfo {
+    info: String,
+    scroll_state: scrollable::State,
+    close_state: button::State,
+}
+
+#[derive(Debug, Clone)]
+pub enum MessageInfoMessage {
+    Close,
+}
+
+impl MessageInfo {
+    pub fn new(info: String) -> Self {
+        Self {
+            info,
+            scroll_state: Default::default(),
+            close_state: Default::default(),
         }
+    }
 
-        button::Button::new(&mut self.button_state, row)
-            .style(Style {})
+    pub fn view(&mut self) -> Element<MessageInfoMessage> {
+        Column::new()
+            .spacing(10)
+            .padding(10)
+            .push(
+                Row::new()
+                    .spacing(10)
+                    .align_items(Align::Center)
+                    .push(
+                        Text::new("Message info")
+                            .color(Color::BLACK)
+                            .size(20)
+                            .width(Length::Fill),
+                    )
+                    .push(
+                        button::Button::new(&mut self.close_state, Text::new("Close").size(16))
+                            .on_press(MessageInfoMessage::Close),
+                    ),
+            )
+            .push(
+                Scrollable::new(&mut self.scroll_state)
+                    .height(Length::Fill)
+                    .push(Text::new(self.info.clone()).size(16).color(Color::BLACK)),
+            )
             .into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use time::OffsetDateTime;
+
+    fn msg(from_id: u32, minutes: i64) -> ChatMsg {
+        let mut msg = account::ChatMessage::pending("hi".into());
+        msg.from_id = from_id;
+        msg.timestamp = OffsetDateTime::unix_epoch() + Duration::minutes(minutes);
+        ChatMsg::new(msg)
+    }
+
+    #[test]
+    fn test_continues() {
+        let first = msg(10, 0);
+
+        assert!(msg(10, 0).continues(&first));
+        assert!(msg(10, 5).continues(&first));
+        assert!(!msg(10, 6).continues(&first));
+        // other sender
+        assert!(!msg(11, 1).continues(&first));
+        // sorted before the previous one
+        assert!(!msg(10, -1).continues(&first));
+
+        let mut info = msg(10, 1);
+        info.msg.is_info = true;
+        assert!(!info.continues(&first));
+        assert!(!msg(10, 2).continues(&info));
+    }
+}
@@ -1,69 +1,664 @@
+use std::collections::BTreeMap;
 use std::path::PathBuf;
+use std::time::Duration;
 
-use iced::{button, image, Color, Column, Element, HorizontalAlignment, Length, Row, Space, Text};
+use deltachat::chat::ChatVisibility;
+use deltachat::constants::Chattype;
+use iced::{
+    button, text_input, Checkbox, Color, Column, Element, HorizontalAlignment, Length, Row, Text,
+    TextInput,
+};
+use time::OffsetDateTime;
 
-use super::{account, chat::ChatMsg};
+use super::{account, account_list::badge, avatar, format};
 
 #[derive(Debug, Clone)]
 pub struct ChatListEntry {
     pub id: deltachat::chat::ChatId,
     pub name: String,
+    /// Prefix of the preview, e.g. "Me" or the sender in groups
+    pub header: String,
     pub preview: String,
     pub profile_image: Option<PathBuf>,
+    pub timestamp: OffsetDateTime,
+    pub fresh_msg_cnt: usize,
+    pub is_group: bool,
+    pub color: u32,
+    pub visibility: ChatVisibility,
+    pub is_muted: bool,
+    /// 1:1 chat with a real contact, who can be blocked
+    pub can_block: bool,
+    /// Whether the actions for this chat are shown
+    pub show_actions: bool,
+    /// Clearing also deletes the messages on the server, so it has to be confirmed
+    pub confirm_clear: bool,
+    pub confirm_delete: bool,
     pub button_state: button::State,
+    actions_state: button::State,
+    archive_state: button::State,
+    pin_state: button::State,
+    mute_states: [button::State; 4],
+    clear_state: button::State,
+    block_state: button::State,
+    delete_state: button::State,
 }
 
 #[derive(Debug, Clone)]
 pub enum ChatListEntryMessage {
     Select,
-    Selected(Vec<ChatMsg>, String),
+    ToggleActions,
+    ClearHistory,
+    Delete,
+    Action(ChatAction),
 }
 
+/// Changes to a chat offered in its context menu.
+#[derive(Debug, Clone, Copy)]
+pub enum ChatAction {
+    SetVisibility(ChatVisibility),
+    /// Mutes for the given time, or forever if `None`
+    Mute(Option<Duration>),
+    Unmute,
+    ClearHistory,
+    BlockContact,
+    LeaveGroup,
+    Delete,
+}
+
+/// Durations offered for muting, with their label.
+const MUTE_DURATIONS: [(&str, Option<Duration>); 4] = [
+    ("1 hour", Some(Duration::from_secs(60 * 60))),
+    ("8 hours", Some(Duration::from_secs(8 * 60 * 60))),
+    ("1 week", Some(Duration::from_secs(7 * 24 * 60 * 60))),
+    ("Forever", None),
+];
+
 impl ChatListEntry {
     pub fn new(chat: &account::ChatState) -> Self {
         Self {
             id: chat.id,
             name: chat.name.clone(),
+            header: chat.header.clone(),
             preview: chat.preview.clone(),
             profile_image: chat.profile_image.clone(),
+            timestamp: chat.timestamp,
+            fresh_msg_cnt: chat.fresh_msg_cnt,
+            is_group: chat.chat_type == Chattype::Group
+                || chat.chat_type == Chattype::VerifiedGroup,
+            color: chat.color,
+            visibility: chat.visibility,
+            is_muted: chat.is_muted,
+            can_block: chat.chat_type == Chattype::Single
+                && !chat.is_self_talk
+                && !chat.is_device_talk,
+            show_actions: false,
+            confirm_clear: false,
+            confirm_delete: false,
             button_state: Default::default(),
+            actions_state: Default::default(),
+            archive_state: Default::default(),
+            pin_state: Default::default(),
+            mute_states: Default::default(),
+            clear_state: Default::default(),
+            block_state: Default::default(),
+            delete_state: Default::default(),
+        }
+    }
+
+    /// Applies the messages that only change the entry, returns the action to run on the chat.
+    ///
+    /// `Select` has to be handled by the caller.
+    pub fn update(&mut self, message: ChatListEntryMessage) -> Option<ChatAction> {
+        match message {
+            ChatListEntryMessage::Select => None,
+            ChatListEntryMessage::ToggleActions => {
+                self.show_actions = !self.show_actions;
+                self.confirm_clear = false;
+                self.confirm_delete = false;
+                None
+            }
+            ChatListEntryMessage::ClearHistory => {
+                self.confirm_clear = true;
+                None
+            }
+            ChatListEntryMessage::Delete => {
+                self.confirm_delete = true;
+                None
+            }
+            ChatListEntryMessage::Action(action) => {
+                self.show_actions = false;
+                Some(action)
+            }
         }
     }
 
-    pub fn view(&mut self) -> Element<ChatListEntryMessage> {
-        let row = Row::new().spacing(20);
-        let row = if let Some(img) = &self.profile_image {
-            let img = image::Image::new(img)
-                .width(Length::Units(60))
-                .height(Length::Units(60));
-            row.push(img)
+    /// Whether both entries show the same content.
+    fn is_unchanged(&self, other: &Self) -> bool {
+        self.id == other.id
+            && self.name == other.name
+            && self.header == other.header
+            && self.preview == other.preview
+            && self.profile_image == other.profile_image
+            && self.timestamp == other.timestamp
+            && self.fresh_msg_cnt == other.fresh_msg_cnt
+            && self.is_group == other.is_group
+            && self.color == other.color
+            && self.visibility == other.visibility
+            && self.is_muted == other.is_muted
+    }
+
+    /// When the last message was sent, chats without one and the archived chats link have none.
+    fn time(&self, time_format: format::TimeFormat) -> Option<String> {
+        if self.timestamp.timestamp() == 0 {
+            return None;
+        }
+
+        Some(format::relative(self.timestamp, time_format))
+    }
+
+    pub fn view(&mut self, time_format: format::TimeFormat) -> Element<ChatListEntryMessage> {
+        let row = Row::new().spacing(20).push(avatar::view(
+            &self.name,
+            self.color,
+            self.profile_image.as_deref(),
+            60,
+        ));
+
+        let mut name = if self.is_group {
+            format!("👥 {}", self.name)
+        } else {
+            self.name.clone()
+        };
+        if self.visibility == ChatVisibility::Pinned {
+            name = format!("📌 {}", name);
+        }
+        if self.is_muted {
+            name = format!("{} 🔕", name);
+        }
+        let preview = if self.header.is_empty() {
+            self.preview.clone()
         } else {
-            row.push(Space::new(Length::Units(60), Length::Units(60)))
+            format!("{}: {}", self.header, self.preview)
         };
 
+        let mut preview_row = Row::new().spacing(5).push(
+            Text::new(preview)
+                .color([0.5, 0.5, 0.5])
+                .horizontal_alignment(HorizontalAlignment::Left)
+                .size(16)
+                .width(Length::Fill),
+        );
+        if self.fresh_msg_cnt > 0 {
+            preview_row = preview_row.push(badge(self.fresh_msg_cnt));
+        }
+
+        let mut title_row = Row::new().spacing(5).push(
+            Text::new(name)
+                .color(Color::BLACK)
+                .horizontal_alignment(HorizontalAlignment::Left)
+                .size(18)
+                .width(Length::Fill),
+        );
+        if let Some(time) = self.time(time_format) {
+            title_row = title_row.push(Text::new(time).color([0.5, 0.5, 0.5]).size(14));
+        }
+
         let row = row.push(
             Column::new()
                 .spacing(5)
                 .max_width(250)
                 .max_height(60)
-                .push(
-                    Text::new(self.name.clone())
-                        .color(Color::BLACK)
-                        .horizontal_alignment(HorizontalAlignment::Left)
-                        .size(18)
-                        .width(Length::Fill),
-                )
-                .push(
-                    Text::new(self.preview.clone())
-                        .color([0.5, 0.5, 0.5])
-                        .horizontal_alignment(HorizontalAlignment::Left)
-                        .size(16)
-                        .width(Length::Fill),
-                ),
+                .push(title_row)
+                .push(preview_row),
         );
 
-        button::Button::new(&mut self.button_state, row)
-            .on_press(ChatListEntryMessage::Select)
+        let select =
+            button::Button::new(&mut self.button_state, row).on_press(ChatListEntryMessage::Select);
+
+        // The link to the archive is not a real chat.
+        if self.id.is_archived_link() {
+            return select.into();
+        }
+
+        let mut column = Column::new().spacing(5).push(
+            Row::new().spacing(5).push(select).push(
+                button::Button::new(&mut self.actions_state, Text::new("⋯").size(16))
+                    .on_press(ChatListEntryMessage::ToggleActions),
+            ),
+        );
+
+        if self.show_actions {
+            column = column.push(self.view_actions());
+        }
+
+        column.into()
+    }
+
+    fn view_actions(&mut self) -> Element<ChatListEntryMessage> {
+        let (pin, pin_to) = if self.visibility == ChatVisibility::Pinned {
+            ("Unpin", ChatVisibility::Normal)
+        } else {
+            ("Pin", ChatVisibility::Pinned)
+        };
+        let (archive, archive_to) = if self.visibility == ChatVisibility::Archived {
+            ("Unarchive", ChatVisibility::Normal)
+        } else {
+            ("Archive", ChatVisibility::Archived)
+        };
+
+        let visibility = Row::new()
+            .spacing(5)
+            .push(action(
+                &mut self.pin_state,
+                pin,
+                ChatAction::SetVisibility(pin_to),
+            ))
+            .push(action(
+                &mut self.archive_state,
+                archive,
+                ChatAction::SetVisibility(archive_to),
+            ));
+
+        let mute = if self.is_muted {
+            Row::new().push(action(
+                &mut self.mute_states[0],
+                "Unmute",
+                ChatAction::Unmute,
+            ))
+        } else {
+            MUTE_DURATIONS.iter().zip(self.mute_states.iter_mut()).fold(
+                Row::new()
+                    .spacing(5)
+                    .push(Text::new("Mute:").color(Color::BLACK).size(14)),
+                |row, ((label, duration), state)| {
+                    row.push(action(state, label, ChatAction::Mute(*duration)))
+                },
+            )
+        };
+
+        let mut danger = Row::new().spacing(5).push(if self.confirm_clear {
+            button::Button::new(
+                &mut self.clear_state,
+                Text::new("Delete all messages, also on the server?")
+                    .size(14)
+                    .color([0.8, 0.0, 0.0]),
+            )
+            .on_press(ChatListEntryMessage::Action(ChatAction::ClearHistory))
+        } else {
+            button::Button::new(&mut self.clear_state, Text::new("Clear history").size(14))
+                .on_press(ChatListEntryMessage::ClearHistory)
+        });
+        if self.can_block {
+            danger = danger.push(action(
+                &mut self.block_state,
+                "Block contact",
+                ChatAction::BlockContact,
+            ));
+        } else if self.is_group {
+            danger = danger.push(action(
+                &mut self.block_state,
+                "Leave group",
+                ChatAction::LeaveGroup,
+            ));
+        }
+        danger = danger.push(if self.confirm_delete {
+            button::Button::new(
+                &mut self.delete_state,
+                Text::new("Delete?").size(14).color([0.8, 0.0, 0.0]),
+            )
+            .on_press(ChatListEntryMessage::Action(ChatAction::Delete))
+        } else {
+            button::Button::new(&mut self.delete_state, Text::new("Delete").size(14))
+                .on_press(ChatListEntryMessage::Delete)
+        });
+
+        Column::new()
+            .spacing(5)
+            .push(visibility)
+            .push(mute)
+            .push(danger)
+            .into()
+    }
+}
+
+fn action<'a>(
+    state: &'a mut button::State,
+    label: &str,
+    action: ChatAction,
+) -> button::Button<'a, ChatListEntryMessage> {
+    button::Button::new(state, Text::new(label).size(14))
+        .on_press(ChatListEntryMessage::Action(action))
+}
+
+/// Brings `entries` into the order of `new`, keeping the entries that did not change
+/// so their widget state survives.
+pub fn apply_diff(entries: &mut Vec<ChatListEntry>, new: Vec<ChatListEntry>) {
+    let mut old = entries
+        .drain(..)
+        .map(|entry| (entry.id, entry))
+        .collect::<BTreeMap<_, _>>();
+
+    entries.extend(
+        new.into_iter()
+            .map(|mut entry| match old.remove(&entry.id) {
+                Some(old) if old.is_unchanged(&entry) => old,
+                Some(old) => {
+                    entry.show_actions = old.show_actions;
+                    entry
+                }
+                None => entry,
+            }),
+    );
+}
+
+/// Search field and filter toggles above the chat list.
+#[derive(Debug, Clone, Default)]
+pub struct ChatSearch {
+    /// The account the filter belongs to
+    pub email: Option<String>,
+    pub filter: account::ChatListFilter,
+    /// Counts changes of the query, so only the last one is searched once typing paused
+    pub query_generation: u64,
+    input_state: text_input::State,
+    back_state: button::State,
+}
+
+#[derive(Debug, Clone)]
+pub enum ChatSearchMessage {
+    QueryChanged(String),
+    UnreadOnly(bool),
+    GroupsOnly(bool),
+    Archived(bool),
+}
+
+impl ChatSearch {
+    /// Applies the message, returns the filter the chat list has to be reloaded with.
+    pub fn update(&mut self, message: ChatSearchMessage) -> account::ChatListFilter {
+        match message {
+            ChatSearchMessage::QueryChanged(query) => {
+                self.filter.query = query;
+                self.query_generation += 1;
+            }
+            ChatSearchMessage::UnreadOnly(value) => self.filter.unread_only = value,
+            ChatSearchMessage::GroupsOnly(value) => self.filter.groups_only = value,
+            ChatSearchMessage::Archived(value) => self.filter.archived = value,
+        }
+
+        self.filter.clone()
+    }
+
+    pub fn view(&mut self) -> Element<ChatSearchMessage> {
+        let mut column = Column::new().spacing(5);
+        if self.filter.archived {
+            column = column.push(
+                button::Button::new(
+                    &mut self.back_state,
+                    Text::new("← Archived chats").color(Color::BLACK).size(18),
+                )
+                .width(Length::Fill)
+                .on_press(ChatSearchMessage::Archived(false)),
+            );
+        }
+
+        column
+            .push(
+                TextInput::new(
+                    &mut self.input_state,
+                    "Search",
+                    &self.filter.query,
+                    ChatSearchMessage::QueryChanged,
+                )
+                .padding(5)
+                .size(16),
+            )
+            .push(
+                Row::new()
+                    .spacing(10)
+                    .push(Checkbox::new(
+                        self.filter.unread_only,
+                        "Unread",
+                        ChatSearchMessage::UnreadOnly,
+                    ))
+                    .push(Checkbox::new(
+                        self.filter.groups_only,
+                        "Groups",
+                        ChatSearchMessage::GroupsOnly,
+                    ))
+                    // With a query the core leaves out the archived chats link, so searching
+                    // archived chats needs the toggle.
+                    .push(Checkbox::new(
+                        self.filter.archived,
+                        "Archived",
+                        ChatSearchMessage::Archived,
+                    )),
+            )
+            .into()
+    }
+}
+
+/// A message found by the chat search, pressing it jumps to the message.
+#[derive(Debug, Clone)]
+pub struct SearchResultEntry {
+    pub result: account::MessageSearchResult,
+    button_state: button::State,
+}
+
+#[derive(Debug, Clone)]
+pub enum SearchResultEntryMessage {
+    Select,
+}
+
+impl SearchResultEntry {
+    pub fn new(result: account::MessageSearchResult) -> Self {
+        Self {
+            result,
+            button_state: Default::default(),
+        }
+    }
+
+    pub fn view(&mut self, time_format: format::TimeFormat) -> Element<SearchResultEntryMessage> {
+        let content = Column::new()
+            .spacing(2)
+            .push(
+                Row::new()
+                    .spacing(5)
+                    .push(
+                        Text::new(self.result.chat_name.clone())
+                            .color(Color::BLACK)
+                            .size(16)
+                            .width(Length::Fill),
+                    )
+                    .push(
+                        Text::new(format::relative(self.result.timestamp, time_format))
+                            .color([0.5, 0.5, 0.5])
+                            .size(14),
+                    ),
+            )
+            .push(
+                Text::new(format!(
+                    "{}: {}",
+                    self.result.from_first_name, self.result.text
+                ))
+                .color([0.5, 0.5, 0.5])
+                .size(14)
+                .width(Length::Fill),
+            );
+
+        button::Button::new(&mut self.button_state, content)
+            .width(Length::Fill)
+            .on_press(SearchResultEntryMessage::Select)
             .into()
     }
 }
+
+/// A contact request in the chat list, with actions to accept, block or ignore it.
+#[derive(Debug, Clone)]
+pub struct ContactRequestEntry {
+    pub request: account::ContactRequest,
+    accept_state: button::State,
+    block_state: button::State,
+    ignore_state: button::State,
+}
+
+#[derive(Debug, Clone)]
+pub enum ContactRequestMessage {
+    Accept,
+    Block,
+    Ignore,
+}
+
+impl ContactRequestEntry {
+    pub fn new(request: account::ContactRequest) -> Self {
+        Self {
+            request,
+            accept_state: Default::default(),
+            block_state: Default::default(),
+            ignore_state: Default::default(),
+        }
+    }
+
+    pub fn view(&mut self, time_format: format::TimeFormat) -> Element<ContactRequestMessage> {
+        let request = &self.request;
+
+        let info = Row::new()
+            .spacing(20)
+            .push(avatar::view(
+                &request.name,
+                request.color,
+                request.profile_image.as_deref(),
+                60,
+            ))
+            .push(
+                Column::new()
+                    .spacing(5)
+                    .push(
+                        Row::new()
+                            .spacing(5)
+                            .push(
+                                Text::new(request.name.clone())
+                                    .color(Color::BLACK)
+                                    .size(18)
+                                    .width(Length::Fill),
+                            )
+                            .push(
+                                Text::new(format::relative(request.timestamp, time_format))
+                                    .color([0.5, 0.5, 0.5])
+                                    .size(14),
+                            ),
+                    )
+                    .push(
+                        Row::new()
+                            .spacing(5)
+                            .push(
+                                Text::new(request.preview.clone())
+                                    .color([0.5, 0.5, 0.5])
+                                    .size(16)
+                                    .width(Length::Fill),
+                            )
+                            .push(badge(request.msg_cnt)),
+                    ),
+            );
+
+        let actions = Row::new()
+            .spacing(5)
+            .push(
+                button::Button::new(&mut self.accept_state, Text::new("Accept").size(14))
+                    .on_press(ContactRequestMessage::Accept),
+            )
+            .push(
+                button::Button::new(
+                    &mut self.block_state,
+                    Text::new("Block").size(14).color([0.8, 0.0, 0.0]),
+                )
+                .on_press(ContactRequestMessage::Block),
+            )
+            .push(
+                button::Button::new(&mut self.ignore_state, Text::new("Ignore").size(14))
+                    .on_press(ContactRequestMessage::Ignore),
+            );
+
+        Column::new().spacing(5).push(info).push(actions).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use deltachat::chat::ChatId;
+
+    fn entry(id: u32, preview: &str) -> ChatListEntry {
+        ChatListEntry::new(&account::ChatState {
+            index: Some(0),
+            id: ChatId::new(id),
+            name: format!("chat {}", id),
+            header: String::new(),
+            preview: preview.to_string(),
+            timestamp: OffsetDateTime::unix_epoch(),
+            state: String::new(),
+            profile_image: None,
+            fresh_msg_cnt: 0,
+            can_send: true,
+            is_self_talk: false,
+            is_device_talk: false,
+            chat_type: Chattype::Single,
+            color: 0,
+            visibility: ChatVisibility::Normal,
+            is_muted: false,
+            generation: 0,
+        })
+    }
+
+    #[test]
+    fn test_is_unchanged() {
+        assert!(entry(10, "hi").is_unchanged(&entry(10, "hi")));
+        assert!(!entry(10, "hi").is_unchanged(&entry(11, "hi")));
+        assert!(!entry(10, "hi").is_unchanged(&entry(10, "hello")));
+
+        let mut muted = entry(10, "hi");
+        muted.is_muted = true;
+        assert!(!entry(10, "hi").is_unchanged(&muted));
+
+        // what the user toggled is not content
+        let mut expanded = entry(10, "hi");
+        expanded.show_actions = true;
+        expanded.confirm_delete = true;
+        assert!(entry(10, "hi").is_unchanged(&expanded));
+    }
+
+    #[test]
+    fn test_time_without_messages() {
+        // chats without messages and the archived chats link have no timestamp
+        assert_eq!(entry(10, "").time(format::TimeFormat::H24), None);
+
+        let mut active = entry(10, "hi");
+        active.timestamp = OffsetDateTime::now_utc();
+        assert!(active.time(format::TimeFormat::H24).is_some());
+    }
+
+    #[test]
+    fn test_apply_diff() {
+        let mut entries = vec![entry(10, "a"), entry(11, "b"), entry(12, "c")];
+        entries[0].show_actions = true;
+        entries[1].show_actions = true;
+        entries[1].confirm_delete = true;
+        entries[2].confirm_delete = true;
+
+        apply_diff(
+            &mut entries,
+            vec![entry(13, "d"), entry(12, "c"), entry(11, "changed")],
+        );
+
+        let ids = entries.iter().map(|entry| entry.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![ChatId::new(13), ChatId::new(12), ChatId::new(11)]);
+
+        // new entries start collapsed
+        assert!(!entries[0].show_actions);
+        // unchanged entries are kept as they are
+        assert!(entries[1].confirm_delete);
+        // changed entries are replaced, but stay expanded
+        assert_eq!(entries[2].preview, "changed");
+        assert!(entries[2].show_actions);
+        assert!(!entries[2].confirm_delete);
+    }
+}
@@ -0,0 +1,240 @@
+use deltachat::constants::Viewtype;
+use iced::{
+    button, image, text_input, Align, Color, Column, Element, HorizontalAlignment, Length, Row,
+    Text, TextInput,
+};
+use iced_native::input::{
+    keyboard::{self, KeyCode},
+    ButtonState,
+};
+
+use crate::media::{self, Attachment};
+
+#[derive(Debug, Clone, Default)]
+pub struct Composer {
+    /// Lines already finished with Shift+Enter.
+    lines: Vec<String>,
+    /// The line that is currently being edited.
+    current: String,
+    shift_pressed: bool,
+    /// File that is sent along with the text, which becomes its caption.
+    attachment: Option<Attachment>,
+    input_state: text_input::State,
+    send_state: button::State,
+    attach_state: button::State,
+    remove_attachment_state: button::State,
+}
+
+#[derive(Debug, Clone)]
+pub enum ComposerMessage {
+    InputChanged(String),
+    Submit,
+    Send,
+    Attach,
+    RemoveAttachment,
+}
+
+/// What the user asked to send.
+#[derive(Debug, Clone)]
+pub enum Outgoing {
+    Text(String),
+    File(Attachment, Option<String>),
+}
+
+impl Composer {
+    /// Applies the message, returns what to send if the user asked to send it.
+    ///
+    /// `Attach` has to be handled by the caller, by picking a file for `set_attachment`.
+    pub fn update(&mut self, message: ComposerMessage) -> Option<Outgoing> {
+        match message {
+            ComposerMessage::InputChanged(value) => {
+                self.current = value;
+                None
+            }
+            ComposerMessage::Submit if self.shift_pressed => {
+                // Shift+Enter starts a new line
+                let line = std::mem::take(&mut self.current);
+                self.lines.push(line);
+                None
+            }
+            ComposerMessage::Submit | ComposerMessage::Send => self.take_outgoing(),
+            ComposerMessage::Attach => None,
+            ComposerMessage::RemoveAttachment => {
+                self.discard_attachment();
+                None
+            }
+        }
+    }
+
+    /// Inserts pasted text, unless the input is focused and already got it.
+    pub fn paste_text(&mut self, text: &str) {
+        if self.input_state.is_focused() {
+            return;
+        }
+
+        let mut lines = text.lines();
+        if let Some(first) = lines.next() {
+            self.current.push_str(first);
+        }
+        for line in lines {
+            let finished = std::mem::replace(&mut self.current, line.to_string());
+            self.lines.push(finished);
+        }
+    }
+
+    /// Starts a reply by quoting a message above what was written so far.
+    ///
+    /// The core has no way to reference the replied message, so it is quoted as text.
+    pub fn quote(&mut self, text: &str) {
+        let mut lines = text
+            .lines()
+            .map(|line| format!("> {}", line))
+            .collect::<Vec<_>>();
+        lines.append(&mut self.lines);
+        self.lines = lines;
+    }
+
+    pub fn has_attachment(&self) -> bool {
+        self.attachment.is_some()
+    }
+
+    pub fn set_attachment(&mut self, attachment: Attachment) {
+        self.discard_attachment();
+        self.attachment = Some(attachment);
+    }
+
+    /// Clears the composer, e.g. when switching chats.
+    pub fn reset(&mut self) {
+        self.discard_attachment();
+        *self = Default::default();
+    }
+
+    fn discard_attachment(&mut self) {
+        if let Some(attachment) = self.attachment.take() {
+            async_std::task::spawn(attachment.discard());
+        }
+    }
+
+    /// Tracks modifier keys, as the text input only reports plain submits.
+    pub fn on_keyboard_event(&mut self, event: keyboard::Event) {
+        if let keyboard::Event::Input {
+            state, key_code, ..
+        } = event
+        {
+            if key_code == KeyCode::LShift || key_code == KeyCode::RShift {
+                self.shift_pressed = state == ButtonState::Pressed;
+            }
+        }
+    }
+
+    /// Whether the event is the paste shortcut, Ctrl+V or Cmd+V.
+    pub fn is_paste(event: &keyboard::Event) -> bool {
+        match event {
+            keyboard::Event::Input {
+                state: ButtonState::Pressed,
+                key_code: KeyCode::V,
+                modifiers,
+            } => modifiers.control || modifiers.logo,
+            _ => false,
+        }
+    }
+
+    fn take_outgoing(&mut self) -> Option<Outgoing> {
+        let mut lines = std::mem::take(&mut self.lines);
+        lines.push(std::mem::take(&mut self.current));
+
+        let text = Some(lines.join("\n")).filter(|text| !text.trim().is_empty());
+        match self.attachment.take() {
+            Some(attachment) => Some(Outgoing::File(attachment, text)),
+            None => text.map(Outgoing::Text),
+        }
+    }
+
+    pub fn view(&mut self, can_send: bool) -> Element<ComposerMessage> {
+        if !can_send {
+            return Text::new("You can not send messages to this chat.")
+                .horizontal_alignment(HorizontalAlignment::Center)
+                .size(16)
+                .color([0.5, 0.5, 0.5])
+                .width(Length::Fill)
+                .into();
+        }
+
+        let lines = self
+            .lines
+            .iter()
+            .fold(Column::new().spacing(2), |column, line| {
+                column.push(Text::new(line.clone()).size(18).color(Color::BLACK))
+            });
+
+        let mut column = Column::new().spacing(5).padding(10);
+        if let Some(attachment) = &self.attachment {
+            let preview: Element<_> = match attachment.viewtype {
+                Viewtype::Image | Viewtype::Gif => image::Image::new(&attachment.path)
+                    .width(Length::Units(80))
+                    .height(Length::Units(80))
+                    .into(),
+                Viewtype::Video => Text::new("🎞").size(40).into(),
+                Viewtype::Audio => Text::new("🎵").size(40).into(),
+                _ => Text::new("📄").size(40).into(),
+            };
+
+            column = column.push(
+                Row::new()
+                    .spacing(10)
+                    .align_items(Align::Center)
+                    .push(preview)
+                    .push(
+                        Column::new()
+                            .spacing(2)
+                            .width(Length::Fill)
+                            .push(Text::new(attachment.name()).size(16).color(Color::BLACK))
+                            .push(
+                                Text::new(format!(
+                                    "{} · {}",
+                                    media::format_size(attachment.file_bytes),
+                                    attachment.mime
+                                ))
+                                .size(14)
+                                .color([0.5, 0.5, 0.5]),
+                            ),
+                    )
+                    .push(
+                        button::Button::new(
+                            &mut self.remove_attachment_state,
+                            Text::new("×").size(18),
+                        )
+                        .on_press(ComposerMessage::RemoveAttachment),
+                    ),
+            );
+        }
+
+        let placeholder = if self.attachment.is_some() {
+            "Add a caption…"
+        } else {
+            "Write a message…"
+        };
+        let input = TextInput::new(
+            &mut self.input_state,
+            placeholder,
+            &self.current,
+            ComposerMessage::InputChanged,
+        )
+        .padding(10)
+        .size(18)
+        .on_submit(ComposerMessage::Submit);
+
+        let attach = button::Button::new(&mut self.attach_state, Text::new("📎").size(18))
+            .padding(10)
+            .on_press(ComposerMessage::Attach);
+
+        let send = button::Button::new(&mut self.send_state, Text::new("Send").size(18))
+            .padding(10)
+            .on_press(ComposerMessage::Send);
+
+        column
+            .push(lines)
+            .push(Row::new().spacing(10).push(attach).push(input).push(send))
+            .into()
+    }
+}
@@ -0,0 +1,37 @@
+use std::path::PathBuf;
+
+/// Asks the user to pick an existing file, optionally restricted to the given patterns.
+pub async fn pick_file(
+    title: &'static str,
+    filter: Option<(&'static [&'static str], &'static str)>,
+) -> Option<PathBuf> {
+    async_std::task::spawn_blocking(move || {
+        tinyfiledialogs::open_file_dialog(title, &default_dir(), filter)
+    })
+    .await
+    .map(PathBuf::from)
+}
+
+/// Asks the user to pick a directory.
+pub async fn pick_folder(title: &'static str) -> Option<PathBuf> {
+    async_std::task::spawn_blocking(move || {
+        tinyfiledialogs::select_folder_dialog(title, &default_dir())
+    })
+    .await
+    .map(PathBuf::from)
+}
+
+/// Asks the user where to save a file, suggesting `name` in the home directory.
+pub async fn save_file(title: &'static str, name: String) -> Option<PathBuf> {
+    async_std::task::spawn_blocking(move || {
+        tinyfiledialogs::save_file_dialog(title, &format!("{}{}", default_dir(), name))
+    })
+    .await
+    .map(PathBuf::from)
+}
+
+fn default_dir() -> String {
+    dirs::home_dir()
+        .map(|dir| format!("{}/", dir.display()))
+        .unwrap_or_default()
+}
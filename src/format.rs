@@ -0,0 +1,180 @@
+use time::{Date, OffsetDateTime, UtcOffset};
+
+use crate::account::HOME_DIR;
+
+/// Remembers the chosen `TimeFormat` across restarts.
+const TIME_FORMAT_FILE: &str = "time-format";
+
+/// How times of the day are shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeFormat {
+    H12,
+    H24,
+}
+
+impl Default for TimeFormat {
+    fn default() -> Self {
+        TimeFormat::H24
+    }
+}
+
+impl TimeFormat {
+    pub const ALL: [TimeFormat; 2] = [TimeFormat::H12, TimeFormat::H24];
+
+    /// Reads the format stored with `save`, falling back to the default.
+    pub fn load() -> Self {
+        match std::fs::read_to_string(HOME_DIR.join(TIME_FORMAT_FILE)) {
+            Ok(s) if s.trim() == "12h" => TimeFormat::H12,
+            Ok(s) if s.trim() == "24h" => TimeFormat::H24,
+            _ => TimeFormat::default(),
+        }
+    }
+
+    pub async fn save(self) -> std::result::Result<(), String> {
+        let value = match self {
+            TimeFormat::H12 => "12h",
+            TimeFormat::H24 => "24h",
+        };
+
+        async_std::fs::create_dir_all(&*HOME_DIR)
+            .await
+            .map_err(|err| err.to_string())?;
+        async_std::fs::write(HOME_DIR.join(TIME_FORMAT_FILE), value)
+            .await
+            .map_err(|err| err.to_string())
+    }
+
+    fn pattern(self) -> &'static str {
+        match self {
+            TimeFormat::H12 => "%-I:%M %p",
+            TimeFormat::H24 => "%H:%M",
+        }
+    }
+}
+
+impl std::fmt::Display for TimeFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TimeFormat::H12 => write!(f, "12-hour"),
+            TimeFormat::H24 => write!(f, "24-hour"),
+        }
+    }
+}
+
+/// Converts a timestamp into the local timezone, at the offset that was valid back then.
+pub fn local(timestamp: OffsetDateTime) -> OffsetDateTime {
+    timestamp.to_offset(UtcOffset::local_offset_at(timestamp))
+}
+
+fn today() -> Date {
+    local(OffsetDateTime::now_utc()).date()
+}
+
+/// Time of the day, e.g. `14:05` or `2:05 PM`.
+pub fn time(timestamp: OffsetDateTime, format: TimeFormat) -> String {
+    local(timestamp).format(format.pattern())
+}
+
+/// Local day of a timestamp, used to find where separators go.
+pub fn day(timestamp: OffsetDateTime) -> Date {
+    local(timestamp).date()
+}
+
+/// Label of a day separator: "Today", "Yesterday", the weekday or the full date.
+pub fn day_label(day: Date) -> String {
+    day_label_at(day, today())
+}
+
+fn day_label_at(day: Date, today: Date) -> String {
+    let days_ago = (today - day).whole_days();
+
+    match days_ago {
+        0 => "Today".into(),
+        1 => "Yesterday".into(),
+        2..=6 => day.format("%A"),
+        _ => day.format("%A, %B %-d, %Y"),
+    }
+}
+
+/// Short time for the chat list, the older the timestamp the coarser it gets.
+pub fn relative(timestamp: OffsetDateTime, format: TimeFormat) -> String {
+    relative_at(timestamp, OffsetDateTime::now_utc(), format)
+}
+
+fn relative_at(timestamp: OffsetDateTime, now: OffsetDateTime, format: TimeFormat) -> String {
+    let minutes = (now - timestamp).whole_minutes();
+    if (0..1).contains(&minutes) {
+        return "now".into();
+    }
+    if (1..60).contains(&minutes) {
+        return format!("{} min", minutes);
+    }
+
+    let today = local(now).date();
+    let local = local(timestamp);
+    match (today - local.date()).whole_days() {
+        0 => local.format(format.pattern()),
+        1 => "Yesterday".into(),
+        2..=6 => local.format("%a"),
+        _ if local.year() == today.year() => local.format("%b %-d"),
+        _ => local.format("%b %-d, %Y"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use time::Duration;
+
+    fn date(year: i32, month: u8, day: u8) -> Date {
+        Date::try_from_ymd(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn test_day_label() {
+        // a monday
+        let today = date(2020, 6, 15);
+
+        assert_eq!(day_label_at(today, today), "Today");
+        assert_eq!(day_label_at(date(2020, 6, 14), today), "Yesterday");
+        assert_eq!(day_label_at(date(2020, 6, 12), today), "Friday");
+        assert_eq!(day_label_at(date(2020, 6, 9), today), "Tuesday");
+        assert_eq!(
+            day_label_at(date(2020, 6, 8), today),
+            "Monday, June 8, 2020"
+        );
+        assert_eq!(
+            day_label_at(date(2019, 12, 31), today),
+            "Tuesday, December 31, 2019"
+        );
+    }
+
+    // Times are at noon UTC, so the local dates match in all but the most extreme timezones.
+    #[test]
+    fn test_relative() {
+        let now = date(2020, 6, 15).midnight().assume_utc() + Duration::hours(12);
+        let format = TimeFormat::H24;
+
+        assert_eq!(relative_at(now, now, format), "now");
+        assert_eq!(relative_at(now - Duration::seconds(59), now, format), "now");
+        assert_eq!(
+            relative_at(now - Duration::minutes(1), now, format),
+            "1 min"
+        );
+        assert_eq!(
+            relative_at(now - Duration::minutes(59), now, format),
+            "59 min"
+        );
+        assert_eq!(
+            relative_at(now - Duration::days(1), now, format),
+            "Yesterday"
+        );
+        assert_eq!(relative_at(now - Duration::days(3), now, format), "Fri");
+        assert_eq!(relative_at(now - Duration::days(30), now, format), "May 16");
+        assert_eq!(
+            relative_at(now - Duration::days(365), now, format),
+            "Jun 16, 2019"
+        );
+    }
+}
@@ -1,9 +1,18 @@
 use iced::{Application, Settings};
 
 mod account;
+mod account_list;
+mod accounts;
 mod app;
+mod avatar;
+mod backup;
 mod chat;
 mod chat_list;
+mod composer;
+mod dialog;
+mod format;
+mod media;
+mod setup;
 
 fn main() {
     femme::start();
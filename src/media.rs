@@ -0,0 +1,356 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use deltachat::constants::Viewtype;
+use lazy_static::lazy_static;
+use log::*;
+
+/// Bounding box for images and videos shown inside the chat.
+pub const MAX_MEDIA_SIZE: (u16, u16) = (300, 300);
+/// Bounding box for stickers, which are shown without a bubble.
+pub const MAX_STICKER_SIZE: (u16, u16) = (160, 160);
+
+/// Scales `width` x `height` down to fit into `max`, keeping the aspect ratio.
+///
+/// Returns `None` if the dimensions are unknown.
+pub fn fit(width: i32, height: i32, max: (u16, u16)) -> Option<(u16, u16)> {
+    if width <= 0 || height <= 0 {
+        return None;
+    }
+
+    let scale = (max.0 as f32 / width as f32)
+        .min(max.1 as f32 / height as f32)
+        .min(1.0);
+
+    Some((
+        ((width as f32 * scale).round() as u16).max(1),
+        ((height as f32 * scale).round() as u16).max(1),
+    ))
+}
+
+/// Name for files derived from `path` in the cache directory.
+///
+/// Uses FNV-1a, as the name has to stay the same across builds and platforms.
+pub fn cache_key(path: &Path) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in path.to_string_lossy().bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    format!("{:016x}", hash)
+}
+
+/// Formats a duration in milliseconds as `m:ss`.
+pub fn format_duration(millis: i32) -> String {
+    let secs = millis.max(0) / 1000;
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+/// Formats a file size in bytes for humans, e.g. `1.4 MB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KB", "MB", "GB", "TB"];
+
+    if bytes < 1000 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64;
+    let mut unit = "B";
+    for next in UNITS {
+        if size < 1000.0 {
+            break;
+        }
+        size /= 1000.0;
+        unit = next;
+    }
+
+    format!("{:.1} {}", size, unit)
+}
+
+/// MIME type of a file, guessed from its extension.
+pub fn mime_type(file: &Path) -> String {
+    mime_guess::from_path(file)
+        .first_or_octet_stream()
+        .to_string()
+}
+
+/// A file picked or dropped by the user, staged for sending.
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub path: PathBuf,
+    pub viewtype: Viewtype,
+    pub mime: String,
+    pub file_bytes: u64,
+    /// The file was created for this attachment by pasting, see `Attachment::discard`
+    pub temporary: bool,
+}
+
+impl Attachment {
+    /// Sniffs the content of `path` to pick the view type, falling back to the extension.
+    pub async fn load(path: PathBuf) -> std::result::Result<Self, String> {
+        let metadata = async_std::fs::metadata(&path)
+            .await
+            .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+        if !metadata.is_file() {
+            return Err(format!("{} is not a file", path.display()));
+        }
+
+        let sniffed = {
+            let path = path.clone();
+            async_std::task::spawn_blocking(move || infer::get_from_path(path)).await
+        };
+        let mime = match sniffed {
+            Ok(Some(typ)) => typ.mime,
+            _ => mime_type(&path),
+        };
+
+        let viewtype = match mime.split('/').next() {
+            Some("image") if mime == "image/gif" => Viewtype::Gif,
+            Some("image") => Viewtype::Image,
+            Some("video") => Viewtype::Video,
+            Some("audio") => Viewtype::Audio,
+            _ => Viewtype::File,
+        };
+
+        Ok(Attachment {
+            path,
+            viewtype,
+            mime,
+            file_bytes: metadata.len(),
+            temporary: false,
+        })
+    }
+
+    /// Deletes the file if it is temporary, when it is not going to be sent.
+    ///
+    /// Sent ones are kept, the core uses files that are in its blobdir in place.
+    pub async fn discard(self) {
+        if !self.temporary {
+            return;
+        }
+
+        if let Err(err) = async_std::fs::remove_file(&self.path).await {
+            warn!("failed to delete {}: {}", self.path.display(), err);
+        }
+    }
+
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// Content of the clipboard.
+#[derive(Debug, Clone)]
+pub enum Pasted {
+    Image(Attachment),
+    Text(String),
+}
+
+/// Reads the clipboard, images are written as a PNG into the blobdir `dir` so they can be sent.
+pub async fn paste(dir: PathBuf) -> std::result::Result<Option<Pasted>, String> {
+    let file = dir.join(format!(
+        "paste-{}.png",
+        time::OffsetDateTime::now_utc().timestamp_nanos()
+    ));
+
+    async_std::task::spawn_blocking(move || {
+        let mut clipboard = arboard::Clipboard::new()
+            .map_err(|err| format!("failed to open clipboard: {}", err))?;
+
+        if let Ok(img) = clipboard.get_image() {
+            std::fs::create_dir_all(&dir)
+                .map_err(|err| format!("failed to create {}: {}", dir.display(), err))?;
+            image::save_buffer(
+                &file,
+                &img.bytes,
+                img.width as u32,
+                img.height as u32,
+                image::ColorType::Rgba8,
+            )
+            .map_err(|err| format!("failed to write {}: {}", file.display(), err))?;
+
+            let file_bytes = std::fs::metadata(&file)
+                .map(|m| m.len())
+                .unwrap_or_default();
+            return Ok(Some(Pasted::Image(Attachment {
+                path: file,
+                viewtype: Viewtype::Image,
+                mime: "image/png".into(),
+                file_bytes,
+                temporary: true,
+            })));
+        }
+
+        Ok(clipboard.get_text().ok().map(Pasted::Text))
+    })
+    .await
+}
+
+/// Puts `text` into the clipboard.
+pub async fn copy_text(text: String) -> std::result::Result<(), String> {
+    async_std::task::spawn_blocking(move || {
+        arboard::Clipboard::new()
+            .and_then(|mut clipboard| clipboard.set_text(text))
+            .map_err(|err| format!("failed to copy to clipboard: {}", err))
+    })
+    .await
+}
+
+/// Opens a file with the default application of the desktop.
+pub async fn open(file: PathBuf) -> std::result::Result<(), String> {
+    async_std::task::spawn_blocking(move || {
+        let mut command = if cfg!(target_os = "windows") {
+            // the empty argument is the window title `start` expects before the file
+            let mut command = std::process::Command::new("cmd");
+            command.args(&["/C", "start", ""]);
+            command
+        } else if cfg!(target_os = "macos") {
+            std::process::Command::new("open")
+        } else {
+            std::process::Command::new("xdg-open")
+        };
+
+        // Waiting also reaps the process, the opened application runs on its own.
+        match command.arg(&file).status() {
+            Ok(status) if status.success() => Ok(()),
+            Ok(status) => Err(format!("failed to open {}: {}", file.display(), status)),
+            Err(err) => Err(format!("failed to open {}: {}", file.display(), err)),
+        }
+    })
+    .await
+}
+
+/// Asks for a target and copies `file` out of the blobdir.
+///
+/// Cancelling the dialog is not an error.
+pub async fn save_as(file: PathBuf) -> std::result::Result<(), String> {
+    let name = file
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    match crate::dialog::save_file("Save as", name).await {
+        Some(target) => async_std::fs::copy(&file, &target)
+            .await
+            .map(|_| ())
+            .map_err(|err| format!("failed to save {}: {}", target.display(), err)),
+        None => Ok(()),
+    }
+}
+
+lazy_static! {
+    /// Videos a poster was generated for, so failures are not retried.
+    static ref POSTER_ATTEMPTS: Mutex<HashSet<PathBuf>> = Default::default();
+}
+
+fn poster_path(file: &Path) -> Option<PathBuf> {
+    Some(
+        dirs::cache_dir()?
+            .join("delta-cool")
+            .join("posters")
+            .join(format!("{}.png", cache_key(file))),
+    )
+}
+
+/// The poster of a video, if `video_poster` generated it already.
+pub fn cached_video_poster(file: &Path) -> Option<PathBuf> {
+    poster_path(file).filter(|poster| poster.exists())
+}
+
+/// Whether a poster for the video still has to be generated with `video_poster`.
+pub fn needs_video_poster(file: &Path) -> bool {
+    !POSTER_ATTEMPTS.lock().unwrap().contains(file) && cached_video_poster(file).is_none()
+}
+
+/// Extracts the first frame of a video using `ffmpeg`, cached in the user cache directory.
+///
+/// Each video is only tried once per run, so a missing `ffmpeg` or a broken video does not
+/// start it over and over.
+pub async fn video_poster(file: PathBuf) -> Option<PathBuf> {
+    if let Some(poster) = cached_video_poster(&file) {
+        return Some(poster);
+    }
+    if !POSTER_ATTEMPTS.lock().unwrap().insert(file.clone()) {
+        return None;
+    }
+
+    let poster = poster_path(&file)?;
+    async_std::task::spawn_blocking(move || {
+        std::fs::create_dir_all(poster.parent()?).ok()?;
+
+        let status = std::process::Command::new("ffmpeg")
+            .args(&["-loglevel", "error", "-i"])
+            .arg(&file)
+            .args(&["-frames:v", "1"])
+            .arg(&poster)
+            .status();
+
+        match status {
+            Ok(status) if status.success() => Some(poster),
+            Ok(status) => {
+                warn!("ffmpeg failed for {}: {}", file.display(), status);
+                None
+            }
+            Err(err) => {
+                warn!("failed to run ffmpeg: {}", err);
+                None
+            }
+        }
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit() {
+        // small enough already
+        assert_eq!(fit(100, 50, (300, 300)), Some((100, 50)));
+        // scaled down keeping the aspect ratio
+        assert_eq!(fit(600, 300, (300, 300)), Some((300, 150)));
+        assert_eq!(fit(300, 900, (300, 300)), Some((100, 300)));
+        assert_eq!(fit(1000, 500, (160, 160)), Some((160, 80)));
+        // never collapses
+        assert_eq!(fit(10000, 1, (300, 300)), Some((300, 1)));
+        // unknown
+        assert_eq!(fit(0, 100, (300, 300)), None);
+        assert_eq!(fit(100, -1, (300, 300)), None);
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0), "0:00");
+        assert_eq!(format_duration(999), "0:00");
+        assert_eq!(format_duration(5_000), "0:05");
+        assert_eq!(format_duration(65_000), "1:05");
+        assert_eq!(format_duration(3_600_000), "60:00");
+        assert_eq!(format_duration(-1), "0:00");
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(999), "999 B");
+        assert_eq!(format_size(1000), "1.0 KB");
+        assert_eq!(format_size(1_400_000), "1.4 MB");
+        assert_eq!(format_size(2_500_000_000), "2.5 GB");
+        assert_eq!(format_size(3_000_000_000_000_000), "3000.0 TB");
+    }
+
+    #[test]
+    fn test_cache_key() {
+        assert_eq!(cache_key(Path::new("")), "cbf29ce484222325");
+        assert_eq!(cache_key(Path::new("a")), "af63dc4c8601ec8c");
+        assert_ne!(
+            cache_key(Path::new("/tmp/a.mp4")),
+            cache_key(Path::new("/tmp/b.mp4"))
+        );
+    }
+}
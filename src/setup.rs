@@ -0,0 +1,316 @@
+use iced::{
+    button, text_input, Color, Column, Element, HorizontalAlignment, Length, ProgressBar, Radio,
+    Row, Text, TextInput,
+};
+
+use crate::account::{Account, Security, ServerSettings};
+
+#[derive(Debug, Default)]
+pub struct Setup {
+    /// The account that is being configured.
+    pub account: Option<Account>,
+    pub email: String,
+    pub password: String,
+    pub imap: ServerSection,
+    pub smtp: ServerSection,
+    /// Configure or import progress, in permille.
+    pub progress: Option<usize>,
+    /// Whether `account` is a temporary account a backup is imported into.
+    pub importing: bool,
+    pub error: Option<String>,
+    show_advanced: bool,
+    /// Whether the setup can be left, when adding an additional account
+    cancelable: bool,
+    email_state: text_input::State,
+    password_state: text_input::State,
+    advanced_state: button::State,
+    login_state: button::State,
+    cancel_state: button::State,
+    import_state: button::State,
+}
+
+#[derive(Debug, Clone)]
+pub enum SetupMessage {
+    EmailChanged(String),
+    PasswordChanged(String),
+    ToggleAdvanced,
+    Imap(ServerMessage),
+    Smtp(ServerMessage),
+    Login,
+    Cancel,
+    ImportBackup,
+}
+
+impl Setup {
+    pub fn cancelable() -> Self {
+        Setup {
+            cancelable: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_configuring(&self) -> bool {
+        self.progress.is_some()
+    }
+
+    /// Identifies the events of the account that is set up.
+    pub fn events_id(&self) -> String {
+        if self.importing {
+            "import".into()
+        } else {
+            crate::account::normalize_email(&self.email)
+        }
+    }
+
+    pub fn start_import(&mut self) {
+        self.error = None;
+        self.progress = Some(0);
+        self.importing = true;
+    }
+
+    /// Resets the progress after configuring or importing failed.
+    ///
+    /// The account of a failed login is kept, so it can be deleted before the next attempt.
+    pub fn fail(&mut self, error: String) {
+        if self.importing {
+            self.account = None;
+        }
+        self.progress = None;
+        self.importing = false;
+        self.error = Some(error);
+    }
+
+    /// Applies the message, returns `true` if the user asked to log in.
+    pub fn update(&mut self, message: SetupMessage) -> bool {
+        match message {
+            SetupMessage::EmailChanged(email) => self.email = email,
+            SetupMessage::PasswordChanged(password) => self.password = password,
+            SetupMessage::ToggleAdvanced => self.show_advanced = !self.show_advanced,
+            SetupMessage::Imap(msg) => self.imap.update(msg),
+            SetupMessage::Smtp(msg) => self.smtp.update(msg),
+            SetupMessage::Cancel | SetupMessage::ImportBackup => {}
+            SetupMessage::Login => {
+                if self.is_configuring() || self.email.trim().is_empty() {
+                    return false;
+                }
+                self.error = None;
+                self.progress = Some(0);
+                return true;
+            }
+        }
+
+        false
+    }
+
+    pub fn view(&mut self) -> Element<SetupMessage> {
+        let configuring = self.is_configuring();
+
+        let mut column = Column::new()
+            .spacing(10)
+            .max_width(500)
+            .push(
+                Text::new("Log in to your account")
+                    .horizontal_alignment(HorizontalAlignment::Center)
+                    .size(30)
+                    .width(Length::Fill),
+            )
+            .push(
+                TextInput::new(
+                    &mut self.email_state,
+                    "Email address",
+                    &self.email,
+                    SetupMessage::EmailChanged,
+                )
+                .padding(10)
+                .size(18),
+            )
+            .push(
+                TextInput::new(
+                    &mut self.password_state,
+                    "Password",
+                    &self.password,
+                    SetupMessage::PasswordChanged,
+                )
+                .password()
+                .padding(10)
+                .size(18)
+                .on_submit(SetupMessage::Login),
+            )
+            .push(
+                button::Button::new(
+                    &mut self.advanced_state,
+                    Text::new(if self.show_advanced {
+                        "Hide advanced settings"
+                    } else {
+                        "Advanced settings"
+                    })
+                    .size(16),
+                )
+                .on_press(SetupMessage::ToggleAdvanced),
+            );
+
+        if self.show_advanced {
+            column = column
+                .push(self.imap.view("Inbox (IMAP)").map(SetupMessage::Imap))
+                .push(self.smtp.view("Outbox (SMTP)").map(SetupMessage::Smtp));
+        }
+
+        let mut login =
+            button::Button::new(&mut self.login_state, Text::new("Log in").size(18)).padding(10);
+        if !configuring {
+            login = login.on_press(SetupMessage::Login);
+        }
+        let mut import =
+            button::Button::new(&mut self.import_state, Text::new("Import backup…").size(18))
+                .padding(10);
+        if !configuring {
+            import = import.on_press(SetupMessage::ImportBackup);
+        }
+
+        let mut buttons = Row::new().spacing(10).push(login).push(import);
+        if self.cancelable {
+            let mut cancel =
+                button::Button::new(&mut self.cancel_state, Text::new("Cancel").size(18))
+                    .padding(10);
+            if !configuring {
+                cancel = cancel.on_press(SetupMessage::Cancel);
+            }
+            buttons = buttons.push(cancel);
+        }
+        column = column.push(buttons);
+
+        if let Some(progress) = self.progress {
+            column = column.push(ProgressBar::new(0.0..=1000.0, progress as f32));
+        }
+
+        if let Some(error) = &self.error {
+            column = column.push(Text::new(error.clone()).size(16).color([0.8, 0.0, 0.0]));
+        }
+
+        column.into()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ServerSection {
+    pub settings: ServerSettings,
+    host_state: text_input::State,
+    port_state: text_input::State,
+    login_state: text_input::State,
+}
+
+#[derive(Debug, Clone)]
+pub enum ServerMessage {
+    HostChanged(String),
+    PortChanged(String),
+    LoginChanged(String),
+    SecurityChanged(Security),
+}
+
+impl ServerSection {
+    fn update(&mut self, message: ServerMessage) {
+        match message {
+            ServerMessage::HostChanged(host) => self.settings.host = host,
+            ServerMessage::PortChanged(port) => {
+                if port.chars().all(|c| c.is_ascii_digit()) {
+                    self.settings.port = port;
+                }
+            }
+            ServerMessage::LoginChanged(login) => self.settings.login = login,
+            ServerMessage::SecurityChanged(security) => self.settings.security = security,
+        }
+    }
+
+    fn view(&mut self, title: &str) -> Element<ServerMessage> {
+        let security = Security::ALL
+            .iter()
+            .fold(Row::new().spacing(10), |row, security| {
+                row.push(Radio::new(
+                    *security,
+                    &security.to_string(),
+                    Some(self.settings.security),
+                    ServerMessage::SecurityChanged,
+                ))
+            });
+
+        Column::new()
+            .spacing(5)
+            .push(Text::new(title).size(20).color(Color::BLACK))
+            .push(
+                TextInput::new(
+                    &mut self.host_state,
+                    "Server",
+                    &self.settings.host,
+                    ServerMessage::HostChanged,
+                )
+                .padding(10)
+                .size(16),
+            )
+            .push(
+                TextInput::new(
+                    &mut self.port_state,
+                    "Port",
+                    &self.settings.port,
+                    ServerMessage::PortChanged,
+                )
+                .padding(10)
+                .size(16),
+            )
+            .push(
+                TextInput::new(
+                    &mut self.login_state,
+                    "Login name",
+                    &self.settings.login,
+                    ServerMessage::LoginChanged,
+                )
+                .padding(10)
+                .size(16),
+            )
+            .push(security)
+            .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_fields() {
+        let mut setup = Setup::default();
+        setup.update(SetupMessage::Imap(ServerMessage::HostChanged(
+            "imap.example.org".into(),
+        )));
+        setup.update(SetupMessage::Imap(ServerMessage::PortChanged("993".into())));
+        setup.update(SetupMessage::Imap(ServerMessage::LoginChanged(
+            "alice".into(),
+        )));
+        setup.update(SetupMessage::Smtp(ServerMessage::SecurityChanged(
+            Security::StartTls,
+        )));
+
+        assert_eq!(setup.imap.settings.host, "imap.example.org");
+        assert_eq!(setup.imap.settings.port, "993");
+        assert_eq!(setup.imap.settings.login, "alice");
+        assert_eq!(setup.imap.settings.security, Security::Automatic);
+        assert_eq!(setup.smtp.settings.security, Security::StartTls);
+        assert_eq!(setup.smtp.settings.host, "");
+
+        // ports only take digits
+        setup.update(SetupMessage::Imap(ServerMessage::PortChanged("99a".into())));
+        assert_eq!(setup.imap.settings.port, "993");
+    }
+
+    #[test]
+    fn test_login_needs_email() {
+        let mut setup = Setup::default();
+        assert!(!setup.update(SetupMessage::Login));
+        assert!(!setup.is_configuring());
+
+        setup.update(SetupMessage::EmailChanged("alice@example.org".into()));
+        assert!(setup.update(SetupMessage::Login));
+        assert!(setup.is_configuring());
+        // a second click while configuring does not start over
+        assert!(!setup.update(SetupMessage::Login));
+    }
+}